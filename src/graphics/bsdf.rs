@@ -0,0 +1,475 @@
+use rand::Rng;
+
+use crate::graphics::film::ThinFilm;
+use crate::graphics::material::{ComplexIor, Principled, Surface};
+use crate::graphics::microfacet::Microfacet;
+use crate::graphics::shape::rand_cos_semisphere;
+use crate::graphics::Color;
+use crate::math::vector::Vector3f;
+use crate::math::{sqr, FloatT, PI};
+
+/// 光线打到表面时散射所需的局部信息
+#[derive(Copy, Clone, Debug)]
+pub struct Interaction {
+    /// 朝向入射一侧的法向量
+    pub normal: Vector3f,
+    /// 入射一侧是否为物体内部
+    pub inside: bool,
+    /// 表面颜色
+    pub color: Color,
+    /// 入射一侧和另一侧的折射率
+    pub ior: (FloatT, FloatT),
+    /// 是否包含漫反射分量，光子映射在视点处单独估计漫反射时为 false
+    pub diffuse: bool,
+    /// 表面的薄膜涂层
+    pub film: Option<ThinFilm>,
+    /// 光谱模式下路径的波长（纳米）
+    pub wavelength: Option<FloatT>,
+}
+
+impl Interaction {
+    /// normal 为表面法向量，dir 为入射光线的方向
+    pub fn new(normal: Vector3f, dir: &Vector3f, color: Color) -> Self {
+        let inside = Vector3f::dot(&normal, dir) > 0.0;
+        Interaction {
+            normal: if inside { -normal } else { normal },
+            inside,
+            color,
+            ior: (1.0, 1.0),
+            diffuse: true,
+            film: None,
+            wavelength: None,
+        }
+    }
+
+    /// 从 w 一侧看同一点，w 在另一侧时翻转法向量并交换两侧的折射率
+    pub fn toward(&self, w: &Vector3f) -> Self {
+        if Vector3f::dot(&self.normal, w) >= 0.0 {
+            *self
+        } else {
+            Interaction {
+                normal: -self.normal,
+                inside: !self.inside,
+                ior: (self.ior.1, self.ior.0),
+                ..*self
+            }
+        }
+    }
+}
+
+/// 按 BSDF 采样得到的出射方向
+pub struct BsdfSample {
+    pub dir: Vector3f,
+    /// f * |cos| / pdf，delta 分布为该分支的权重
+    pub weight: Color,
+    /// 立体角测度下的概率密度，delta 分布为 None
+    pub pdf: Option<FloatT>,
+    /// 是否穿过表面到了另一侧
+    pub refracted: bool,
+}
+
+/// 双向散射分布函数，wo 指向入射光线的来处，wi 为散射后的方向
+pub trait Bsdf {
+    /// 按 BSDF 重要性采样出射方向，失败时返回 None，路径应当终止
+    fn sample<R: Rng>(&self, it: &Interaction, wo: &Vector3f, rng: &mut R) -> Option<BsdfSample>;
+    /// BSDF 的值，不含余弦项，delta 分布为零
+    fn eval(&self, it: &Interaction, wo: &Vector3f, wi: &Vector3f) -> Color;
+    /// 按 sample 采样到 wi 的概率密度（立体角测度），delta 分布为零
+    fn pdf(&self, it: &Interaction, wo: &Vector3f, wi: &Vector3f) -> FloatT;
+    /// 是否为 delta 分布，此时无法求值，只能沿采样的方向散射
+    fn is_delta(&self) -> bool;
+    /// delta 分布的所有分支及各自的权重，用于确定性地追踪；非 delta 分布返回空
+    fn branches(&self, it: &Interaction, wo: &Vector3f) -> Vec<BsdfSample>;
+    /// 漫反射分量的反照率，光子映射在这一部分上做密度估计
+    fn diffuse(&self, it: &Interaction) -> Color;
+}
+
+/// 入射方向 i 在法向量为 normal（与 i 相对）的界面上从折射率 n 射向 nt
+/// 返回反射光强占比和 (透射光强占比, 折射光方向)，全反射时没有后者
+pub fn refract(
+    i: &Vector3f,
+    normal: &Vector3f,
+    n: FloatT,
+    nt: FloatT,
+) -> (FloatT, Option<(FloatT, Vector3f)>) {
+    let nt2 = nt * nt;
+    let dn = Vector3f::dot(i, normal);
+    let delta = { nt2 - n * n * (1.0 - dn * dn) };
+
+    // 全反射
+    if delta <= 0.0 {
+        (1.0, None)
+    } else {
+        // 折射光方向
+        let t = (n * (*i - *normal * dn) / nt - *normal * (delta / nt2).sqrt()).normalized();
+        assert!(Vector3f::dot(&t, normal) <= 0.0);
+
+        // 计算反射光强占比
+        let r = {
+            let r0 = sqr((nt - n) / (nt + n));
+            let c = if n <= nt {
+                Vector3f::dot(i, normal).abs()
+            } else {
+                Vector3f::dot(&t, &normal).abs()
+            };
+            r0 + (1.0 - r0) * (1.0 - c).powi(5)
+        };
+        (r, Some((1.0 - r, t)))
+    }
+}
+
+fn reflect(i: &Vector3f, normal: &Vector3f) -> Vector3f {
+    *i - *normal * 2.0 * Vector3f::dot(normal, i)
+}
+
+// Schlick 近似的 Fresnel 系数
+fn schlick(f0: Color, cos: FloatT) -> Color {
+    f0 + (Color::ones() - f0) * (1.0 - cos).max(0.0).powi(5)
+}
+
+// 电介质界面的反射率，cos 为入射角余弦，re 为没有薄膜时的 Fresnel 反射率
+fn dielectric_fresnel(it: &Interaction, cos: FloatT, re: FloatT) -> Color {
+    match it.film {
+        Some(film) => film.reflectance(
+            cos,
+            it.ior.0,
+            Color::full(it.ior.1),
+            Color::empty(),
+            it.wavelength,
+        ),
+        None => Color::full(re),
+    }
+}
+
+// 导体的 Fresnel 反射率，有薄膜时计入干涉
+fn conductor_fresnel(ior: &ComplexIor, it: &Interaction, cos: FloatT) -> Color {
+    match it.film {
+        Some(film) => {
+            let (eta, k) = ior.eta_k();
+            film.reflectance(cos, it.ior.0, eta, k, it.wavelength)
+        }
+        None => ior.fresnel(cos),
+    }
+}
+
+// 按微表面法向量反射，出射方向落在另一侧时返回 None
+fn sample_reflect<R: Rng>(
+    microfacet: &Microfacet,
+    it: &Interaction,
+    wo: &Vector3f,
+    rng: &mut R,
+) -> Option<Vector3f> {
+    let dir = reflect(&-*wo, &microfacet.sample(&it.normal, wo, rng));
+    if Vector3f::dot(&dir, &it.normal) > 0.0 {
+        Some(dir)
+    } else {
+        None
+    }
+}
+
+// 粗糙电介质按 Fresnel 系数随机选择反射或折射，出射方向落在错误一侧时返回 None
+fn sample_dielectric<R: Rng>(
+    microfacet: &Microfacet,
+    it: &Interaction,
+    wo: &Vector3f,
+    rng: &mut R,
+) -> Option<Vector3f> {
+    let h = microfacet.sample(&it.normal, wo, rng);
+    let (n, nt) = it.ior;
+    let (dir, refracted) = match refract(&-*wo, &h, n, nt).1 {
+        Some((tr, t)) if rng.gen_range(0.0, 1.0) < tr => (t, true),
+        _ => (reflect(&-*wo, &h), false),
+    };
+    if (Vector3f::dot(&dir, &it.normal) < 0.0) == refracted {
+        Some(dir)
+    } else {
+        None
+    }
+}
+
+// 粗糙电介质反射部分和折射部分的 BSDF 值（已乘 Fresnel 系数）及概率密度
+// 概率密度按没有薄膜时的 Fresnel 系数选择反射或折射
+fn eval_dielectric(
+    microfacet: &Microfacet,
+    it: &Interaction,
+    wo: &Vector3f,
+    wi: &Vector3f,
+) -> (Color, Color, FloatT) {
+    let (n, nt) = it.ior;
+    let none = (Color::empty(), Color::empty(), 0.0);
+    if Vector3f::dot(&it.normal, wi) > 0.0 {
+        let h = (*wo + *wi).normalized();
+        let re = refract(&-*wo, &h, n, nt).0;
+        match microfacet.reflect(&it.normal, wo, wi) {
+            Some((f, pdf)) => (
+                dielectric_fresnel(it, Vector3f::dot(wo, &h), re) * f,
+                Color::empty(),
+                re * pdf,
+            ),
+            None => none,
+        }
+    } else {
+        match microfacet.transmit(&it.normal, wo, wi, n, nt) {
+            Some((h, f, pdf)) => {
+                let re = refract(&-*wo, &h, n, nt).0;
+                let tr = Color::ones() - dielectric_fresnel(it, Vector3f::dot(wo, &h), re);
+                (Color::empty(), tr * f, (1.0 - re) * pdf)
+            }
+            None => none,
+        }
+    }
+}
+
+// principled 材质在透射物体内部时只有粗糙电介质的反射和折射
+fn dielectric_inside(principled: &Principled, it: &Interaction) -> bool {
+    it.inside && principled.transmission > 0.0
+}
+
+// 透射部分每穿过一次表面乘上颜色的平方根，进出一次恰为颜色
+// 透射比例只在从外部进入时计入一次，内部的反射和折射不再乘它
+fn transmission_tint(color: Color) -> Color {
+    Color::new([color[0].sqrt(), color[1].sqrt(), color[2].sqrt()])
+}
+
+fn sample_principled<R: Rng>(
+    principled: &Principled,
+    it: &Interaction,
+    wo: &Vector3f,
+    rng: &mut R,
+) -> Option<Vector3f> {
+    let microfacet = principled.microfacet();
+    if dielectric_inside(principled, it) {
+        return sample_dielectric(&microfacet, it, wo, rng);
+    }
+    let [p_diffuse, p_specular, p_clearcoat, p_transmission] = principled.lobes(it.diffuse);
+    let u = rng.gen_range(0.0, 1.0);
+    if u < p_diffuse {
+        Some(rand_cos_semisphere(&it.normal, rng))
+    } else if u < p_diffuse + p_specular || p_clearcoat + p_transmission <= 0.0 {
+        sample_reflect(&microfacet, it, wo, rng)
+    } else if u < p_diffuse + p_specular + p_clearcoat || p_transmission <= 0.0 {
+        sample_reflect(&principled.clearcoat_microfacet(), it, wo, rng)
+    } else {
+        // 透射分量只取 Fresnel 系数中折射的部分，反射的部分已包含在镜面反射中
+        let h = microfacet.sample(&it.normal, wo, rng);
+        let (n, nt) = it.ior;
+        let (_, t) = refract(&-*wo, &h, n, nt).1?;
+        if Vector3f::dot(&t, &it.normal) < 0.0 {
+            Some(t)
+        } else {
+            None
+        }
+    }
+}
+
+fn eval_principled(
+    principled: &Principled,
+    it: &Interaction,
+    wo: &Vector3f,
+    wi: &Vector3f,
+) -> Color {
+    let microfacet = principled.microfacet();
+    let tint = transmission_tint(it.color);
+    if dielectric_inside(principled, it) {
+        // principled 材质不使用薄膜
+        let it = Interaction { film: None, ..*it };
+        let (reflect, transmit, _) = eval_dielectric(&microfacet, &it, wo, wi);
+        return reflect + tint * transmit;
+    }
+    let (n, nt) = it.ior;
+    if Vector3f::dot(&it.normal, wi) <= 0.0 {
+        return match microfacet.transmit(&it.normal, wo, wi, n, nt) {
+            Some((h, f, _)) if principled.transmission > 0.0 => {
+                tint * (principled.transmission_weight() * (1.0 - refract(&-*wo, &h, n, nt).0) * f)
+            }
+            _ => Color::empty(),
+        };
+    }
+    let specular = match microfacet.reflect(&it.normal, wo, wi) {
+        Some((f, _)) => f,
+        None => return Color::empty(),
+    };
+    let cos_h = Vector3f::dot(wo, &(*wo + *wi).normalized());
+    let f0 = Color::full(0.08 * principled.specular) * (1.0 - principled.metallic)
+        + it.color * principled.metallic;
+    let mut f = schlick(f0, cos_h) * specular;
+    if it.diffuse {
+        f += it.color * (principled.diffuse_weight() / PI);
+    }
+    if principled.clearcoat > 0.0 {
+        if let Some((clearcoat, _)) = principled
+            .clearcoat_microfacet()
+            .reflect(&it.normal, wo, wi)
+        {
+            f += schlick(Color::full(0.04), cos_h) * (principled.clearcoat * clearcoat);
+        }
+    }
+    f
+}
+
+fn pdf_principled(
+    principled: &Principled,
+    it: &Interaction,
+    wo: &Vector3f,
+    wi: &Vector3f,
+) -> FloatT {
+    let microfacet = principled.microfacet();
+    if dielectric_inside(principled, it) {
+        return eval_dielectric(&microfacet, it, wo, wi).2;
+    }
+    let [p_diffuse, p_specular, p_clearcoat, p_transmission] = principled.lobes(it.diffuse);
+    let cos = Vector3f::dot(&it.normal, wi);
+    if cos <= 0.0 {
+        let (n, nt) = it.ior;
+        return microfacet
+            .transmit(&it.normal, wo, wi, n, nt)
+            .map_or(0.0, |(_, _, pdf)| p_transmission * pdf);
+    }
+    let pdf = |microfacet: Microfacet| microfacet.reflect(&it.normal, wo, wi).map_or(0.0, |r| r.1);
+    p_diffuse * cos / PI
+        + p_specular * pdf(microfacet)
+        + p_clearcoat * pdf(principled.clearcoat_microfacet())
+}
+
+impl Bsdf for Surface {
+    fn sample<R: Rng>(&self, it: &Interaction, wo: &Vector3f, rng: &mut R) -> Option<BsdfSample> {
+        let dir = match self {
+            Surface::Diffuse if it.diffuse => rand_cos_semisphere(&it.normal, rng),
+            Surface::Diffuse => return None,
+            _ if self.is_delta() => {
+                // 按权重随机选择一个分支，选择概率与权重相消
+                let mut branches = self.branches(it, wo);
+                let total = branches.iter().map(|b| b.weight.norm1()).sum::<FloatT>();
+                if total <= 0.0 {
+                    return None;
+                }
+                let mut u = rng.gen_range(0.0, total);
+                while branches.len() > 1 && u >= branches[0].weight.norm1() {
+                    u -= branches.remove(0).weight.norm1();
+                }
+                let mut branch = branches.remove(0);
+                branch.weight = branch.weight * (total / branch.weight.norm1());
+                return Some(branch);
+            }
+            Surface::Glossy { .. } => sample_reflect(&self.microfacet().unwrap(), it, wo, rng)?,
+            Surface::RoughRefractive { .. } => {
+                sample_dielectric(&self.microfacet().unwrap(), it, wo, rng)?
+            }
+            Surface::Principled(principled) => sample_principled(principled, it, wo, rng)?,
+            Surface::Conductor { .. } => sample_reflect(&self.microfacet().unwrap(), it, wo, rng)?,
+            Surface::Specular | Surface::Refractive(_) | Surface::Subsurface(_) => unreachable!(),
+        };
+        let pdf = self.pdf(it, wo, &dir);
+        if !(pdf > 0.0) {
+            return None;
+        }
+        let cos = Vector3f::dot(&it.normal, &dir);
+        Some(BsdfSample {
+            dir,
+            weight: self.eval(it, wo, &dir) * (cos.abs() / pdf),
+            pdf: Some(pdf),
+            refracted: cos < 0.0,
+        })
+    }
+
+    fn eval(&self, it: &Interaction, wo: &Vector3f, wi: &Vector3f) -> Color {
+        match self {
+            // 漫反射两面都能反射，但入射和出射要在同一侧
+            Surface::Diffuse if it.diffuse && Vector3f::dot(&it.normal, wi) > 0.0 => it.color / PI,
+            Surface::Glossy { .. } => self
+                .microfacet()
+                .unwrap()
+                .reflect(&it.normal, wo, wi)
+                .map_or(Color::empty(), |(f, _)| it.color * f),
+            Surface::RoughRefractive { .. } => {
+                let (reflect, transmit, _) =
+                    eval_dielectric(&self.microfacet().unwrap(), it, wo, wi);
+                it.color * (reflect + transmit)
+            }
+            Surface::Principled(principled) => eval_principled(principled, it, wo, wi),
+            Surface::Conductor { ior, .. } if !self.is_delta() => {
+                match self.microfacet().unwrap().reflect(&it.normal, wo, wi) {
+                    Some((f, _)) => {
+                        let h = (*wo + *wi).normalized();
+                        it.color * conductor_fresnel(ior, it, Vector3f::dot(wo, &h)) * f
+                    }
+                    None => Color::empty(),
+                }
+            }
+            _ => Color::empty(),
+        }
+    }
+
+    fn pdf(&self, it: &Interaction, wo: &Vector3f, wi: &Vector3f) -> FloatT {
+        match self {
+            Surface::Diffuse if it.diffuse => Vector3f::dot(&it.normal, wi).max(0.0) / PI,
+            Surface::Glossy { .. } => self
+                .microfacet()
+                .unwrap()
+                .reflect(&it.normal, wo, wi)
+                .map_or(0.0, |(_, pdf)| pdf),
+            Surface::RoughRefractive { .. } => {
+                eval_dielectric(&self.microfacet().unwrap(), it, wo, wi).2
+            }
+            Surface::Principled(principled) => pdf_principled(principled, it, wo, wi),
+            Surface::Conductor { .. } if !self.is_delta() => self
+                .microfacet()
+                .unwrap()
+                .reflect(&it.normal, wo, wi)
+                .map_or(0.0, |(_, pdf)| pdf),
+            _ => 0.0,
+        }
+    }
+
+    fn is_delta(&self) -> bool {
+        match self {
+            Surface::Specular | Surface::Refractive(_) | Surface::Subsurface(_) => true,
+            Surface::Conductor { roughness, .. } => *roughness <= 0.0,
+            _ => false,
+        }
+    }
+
+    fn branches(&self, it: &Interaction, wo: &Vector3f) -> Vec<BsdfSample> {
+        let i = -*wo;
+        let reflected = |weight| BsdfSample {
+            dir: reflect(&i, &it.normal),
+            weight,
+            pdf: None,
+            refracted: false,
+        };
+        match self {
+            Surface::Specular => vec![reflected(it.color)],
+            Surface::Conductor { ior, .. } if self.is_delta() => {
+                vec![reflected(
+                    it.color * conductor_fresnel(ior, it, Vector3f::dot(wo, &it.normal)),
+                )]
+            }
+            Surface::Refractive(_) | Surface::Subsurface(_) => {
+                let (n, nt) = it.ior;
+                let (re, tr) = refract(&i, &it.normal, n, nt);
+                let re = dielectric_fresnel(it, Vector3f::dot(wo, &it.normal), re);
+                let mut branches = vec![reflected(it.color * re)];
+                if let Some((_, t)) = tr {
+                    branches.push(BsdfSample {
+                        dir: t,
+                        weight: it.color * (Color::ones() - re),
+                        pdf: None,
+                        refracted: true,
+                    });
+                }
+                branches
+            }
+            _ => vec![],
+        }
+    }
+
+    fn diffuse(&self, it: &Interaction) -> Color {
+        match self {
+            Surface::Diffuse => it.color,
+            Surface::Principled(principled) if !dielectric_inside(principled, it) => {
+                it.color * principled.diffuse_weight()
+            }
+            _ => Color::empty(),
+        }
+    }
+}
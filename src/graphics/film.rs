@@ -0,0 +1,120 @@
+use std::ops::{Add, Div, Mul, Sub};
+
+use serde::Deserialize;
+
+use crate::graphics::Color;
+use crate::math::{FloatT, PI};
+
+/// 不区分波长时 RGB 三个通道对应的波长（纳米）
+pub const CHANNEL_WAVELENGTHS: [FloatT; 3] = [650.0, 550.0, 450.0];
+
+/// 表面上的薄膜涂层，反射光与薄膜下表面的反射光干涉，反射率随波长和角度变化
+#[derive(Copy, Clone, Deserialize, Debug)]
+pub struct ThinFilm {
+    /// 厚度（纳米）
+    pub thickness: FloatT,
+    /// 薄膜的折射率
+    pub ior: FloatT,
+}
+
+#[derive(Copy, Clone)]
+struct Complex(FloatT, FloatT);
+
+impl Complex {
+    fn real(x: FloatT) -> Self {
+        Complex(x, 0.0)
+    }
+
+    fn norm2(self) -> FloatT {
+        self.0 * self.0 + self.1 * self.1
+    }
+
+    fn sqrt(self) -> Self {
+        let r = self.norm2().sqrt();
+        let re = ((r + self.0) / 2.0).max(0.0).sqrt();
+        let im = ((r - self.0) / 2.0).max(0.0).sqrt();
+        Complex(re, if self.1 < 0.0 { -im } else { im })
+    }
+
+    /// e^(i * self)
+    fn exp_i(self) -> Self {
+        let r = (-self.1).exp();
+        Complex(r * self.0.cos(), r * self.0.sin())
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+    fn add(self, rhs: Complex) -> Complex {
+        Complex(self.0 + rhs.0, self.1 + rhs.1)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+    fn sub(self, rhs: Complex) -> Complex {
+        Complex(self.0 - rhs.0, self.1 - rhs.1)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+    fn mul(self, rhs: Complex) -> Complex {
+        Complex(
+            self.0 * rhs.0 - self.1 * rhs.1,
+            self.0 * rhs.1 + self.1 * rhs.0,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+    fn div(self, rhs: Complex) -> Complex {
+        let d = rhs.norm2();
+        Complex(
+            (self.0 * rhs.0 + self.1 * rhs.1) / d,
+            (self.1 * rhs.0 - self.0 * rhs.1) / d,
+        )
+    }
+}
+
+// 从折射率 n_i 射向 n_t 的界面上 s 偏振和 p 偏振的振幅反射系数
+fn amplitude(n_i: Complex, cos_i: Complex, n_t: Complex, cos_t: Complex) -> (Complex, Complex) {
+    let s = (n_i * cos_i - n_t * cos_t) / (n_i * cos_i + n_t * cos_t);
+    let p = (n_t * cos_i - n_i * cos_t) / (n_t * cos_i + n_i * cos_t);
+    (s, p)
+}
+
+impl ThinFilm {
+    /// 从折射率 n 的一侧以入射角余弦 cos 照到薄膜上，薄膜下为复折射率 eta + i k 的基底时各通道的反射率
+    /// 电介质基底的 k 为零；wavelength（纳米）为 None 时各通道取 `CHANNEL_WAVELENGTHS`
+    pub fn reflectance(
+        &self,
+        cos: FloatT,
+        n: FloatT,
+        eta: Color,
+        k: Color,
+        wavelength: Option<FloatT>,
+    ) -> Color {
+        let cos = cos.abs().min(1.0);
+        // 折射不变量 n sinθ 的平方
+        let sin2 = Complex::real(n * n * (1.0 - cos * cos));
+        let cos_t = |n_t: Complex| (Complex::real(1.0) - sin2 / (n_t * n_t)).sqrt();
+        let (n1, n2) = (Complex::real(n), Complex::real(self.ior));
+        let (cos1, cos2) = (Complex::real(cos), cos_t(n2));
+        let (r12_s, r12_p) = amplitude(n1, cos1, n2, cos2);
+        let mut r = Color::empty();
+        for i in 0..3 {
+            let n3 = Complex(eta[i], k[i]);
+            let (r23_s, r23_p) = amplitude(n2, cos2, n3, cos_t(n3));
+            let wavelength = wavelength.unwrap_or(CHANNEL_WAVELENGTHS[i]);
+            // 在薄膜中往返一次的相位差
+            let phase = (Complex::real(4.0 * PI * self.thickness / wavelength) * n2 * cos2).exp_i();
+            let airy = |r12: Complex, r23: Complex| {
+                ((r12 + r23 * phase) / (Complex::real(1.0) + r12 * r23 * phase)).norm2()
+            };
+            r[i] = ((airy(r12_s, r23_s) + airy(r12_p, r23_p)) / 2.0).min(1.0);
+        }
+        r
+    }
+}
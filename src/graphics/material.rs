@@ -1,26 +1,324 @@
 use serde::Deserialize;
 
-use crate::graphics::Color;
+use crate::graphics::film::ThinFilm;
+use crate::graphics::medium::Medium;
+use crate::graphics::microfacet::{Distribution, Microfacet};
+use crate::graphics::{luminance, Color};
 use crate::math::vector::Vector3f;
 use crate::math::{FloatT, Ray};
 use crate::utils::Image;
 
-#[derive(Deserialize, Debug)]
+#[derive(Clone, Deserialize, Debug)]
 pub enum Texture {
     Pure(Color),
     Image(Image),
 }
 
+impl Texture {
+    // 对所有纹素的颜色做折叠
+    fn fold<T>(&self, init: T, f: impl Fn(T, Color) -> T) -> T {
+        match self {
+            Texture::Pure(color) => f(init, *color),
+            Texture::Image(image) => (0..image.w)
+                .flat_map(|x| (0..image.h).map(move |y| (x, y)))
+                .fold(init, |acc, (x, y)| f(acc, image.at(x, y))),
+        }
+    }
+
+    /// 所有纹素的平均颜色
+    pub fn average(&self) -> Color {
+        let count = match self {
+            Texture::Pure(_) => 1,
+            Texture::Image(image) => image.w * image.h,
+        };
+        self.fold(Color::empty(), |sum, color| sum + color) / count as FloatT
+    }
+
+    /// 纹素亮度的最大值
+    pub fn max_luminance(&self) -> FloatT {
+        self.fold(0.0, |max, color| max.max(luminance(&color)))
+    }
+}
+
 // 表面光学特性
 #[derive(Copy, Clone, Deserialize, Debug)]
 pub enum Surface {
-    Specular,           // 镜面
-    Diffuse,            // 漫反射
-    Refractive(FloatT), // 折射(折射率)
+    Specular,        // 镜面
+    Diffuse,         // 漫反射
+    Refractive(Ior), // 折射(折射率)
+    /// 粗糙导体，颜色即反射率
+    Glossy {
+        roughness: FloatT,
+        #[serde(default)]
+        distribution: Distribution,
+    },
+    /// 粗糙电介质，同时反射和折射
+    RoughRefractive {
+        ior: Ior,
+        roughness: FloatT,
+        #[serde(default)]
+        distribution: Distribution,
+    },
+    /// 混合漫反射、镜面反射、透射和清漆层的材质
+    Principled(Principled),
+    /// 由复折射率给出 Fresnel 反射率的金属，roughness 为 0 时为光滑镜面，颜色作为额外的染色
+    Conductor {
+        ior: ComplexIor,
+        #[serde(default)]
+        roughness: FloatT,
+        #[serde(default)]
+        distribution: Distribution,
+    },
+    /// 次表面散射，边界为光滑电介质，内部为随机游走的均匀介质
+    Subsurface(Subsurface),
+}
+
+impl Surface {
+    /// 粗糙表面的微表面模型
+    pub fn microfacet(&self) -> Option<Microfacet> {
+        match *self {
+            Surface::Glossy {
+                roughness,
+                distribution,
+            }
+            | Surface::RoughRefractive {
+                roughness,
+                distribution,
+                ..
+            } => Some(Microfacet::new(distribution, roughness)),
+            Surface::Conductor {
+                roughness,
+                distribution,
+                ..
+            } if roughness > 0.0 => Some(Microfacet::new(distribution, roughness)),
+            _ => None,
+        }
+    }
+
+    /// 会折射的表面另一侧的折射率，wavelength（纳米）为 None 时取标称值
+    pub fn ior(&self, wavelength: Option<FloatT>) -> Option<FloatT> {
+        let at = |ior: &Ior| wavelength.map_or(ior.nominal(), |wavelength| ior.at(wavelength));
+        match self {
+            Surface::Refractive(ior) | Surface::RoughRefractive { ior, .. } => Some(at(ior)),
+            Surface::Subsurface(subsurface) => Some(subsurface.ior),
+            Surface::Principled(principled) if principled.transmission > 0.0 => {
+                Some(principled.ior())
+            }
+            _ => None,
+        }
+    }
+}
+
+fn default_subsurface_ior() -> FloatT {
+    1.4
+}
+
+/// 次表面散射材质的参数，内部介质由平均自由程和单次散射反照率确定，只对封闭的物体有效
+#[derive(Copy, Clone, Deserialize, Debug)]
+pub struct Subsurface {
+    #[serde(default = "default_subsurface_ior")]
+    pub ior: FloatT,
+    /// 各通道的平均自由程，即消光系数的倒数
+    pub mean_free_path: Color,
+    /// 单次散射反照率，散射系数占消光系数的比例
+    pub albedo: Color,
+    /// Henyey-Greenstein 相函数的不对称参数
+    #[serde(default)]
+    pub g: FloatT,
+}
+
+impl Subsurface {
+    /// 物体内部的均匀介质
+    pub fn medium(&self) -> Medium {
+        let extinction = Color::new([
+            1.0 / self.mean_free_path[0],
+            1.0 / self.mean_free_path[1],
+            1.0 / self.mean_free_path[2],
+        ]);
+        Medium {
+            absorption: (Color::ones() - self.albedo) * extinction,
+            scattering: self.albedo * extinction,
+            g: self.g,
+            grid: None,
+        }
+    }
+}
+
+fn default_specular() -> FloatT {
+    0.5
+}
+
+fn default_clearcoat_roughness() -> FloatT {
+    0.1
+}
+
+/// 参考 Disney principled BSDF 的分层材质，基础颜色取自纹理，各参数都在 [0, 1] 中
+#[derive(Copy, Clone, Deserialize, Debug)]
+pub struct Principled {
+    /// 金属度，金属只有带颜色的镜面反射
+    #[serde(default)]
+    pub metallic: FloatT,
+    pub roughness: FloatT,
+    /// 非金属的镜面反射强度，0.5 对应折射率 1.5
+    #[serde(default = "default_specular")]
+    pub specular: FloatT,
+    /// 透射比例，透射部分为粗糙电介质
+    #[serde(default)]
+    pub transmission: FloatT,
+    /// 表面清漆层的强度
+    #[serde(default)]
+    pub clearcoat: FloatT,
+    #[serde(default = "default_clearcoat_roughness")]
+    pub clearcoat_roughness: FloatT,
+}
+
+impl Principled {
+    /// 非金属部分的折射率，由法向入射时的反射率 0.08 * specular 反推
+    pub fn ior(&self) -> FloatT {
+        let f0 = (0.08 * self.specular).sqrt();
+        (1.0 + f0) / (1.0 - f0)
+    }
+
+    pub fn microfacet(&self) -> Microfacet {
+        Microfacet::new(Distribution::GGX, self.roughness)
+    }
+
+    pub fn clearcoat_microfacet(&self) -> Microfacet {
+        Microfacet::new(Distribution::GGX, self.clearcoat_roughness)
+    }
+
+    /// 漫反射部分的比例
+    pub fn diffuse_weight(&self) -> FloatT {
+        (1.0 - self.metallic) * (1.0 - self.transmission)
+    }
+
+    /// 透射部分的比例
+    pub fn transmission_weight(&self) -> FloatT {
+        (1.0 - self.metallic) * self.transmission
+    }
+
+    /// 漫反射、镜面反射、清漆、透射四个分量的选择概率，diffuse 为 false 时不含漫反射
+    pub fn lobes(&self, diffuse: bool) -> [FloatT; 4] {
+        let mut lobes = [
+            if diffuse { self.diffuse_weight() } else { 0.0 },
+            1.0,
+            self.clearcoat,
+            self.transmission_weight(),
+        ];
+        let sum = lobes.iter().sum::<FloatT>();
+        for lobe in &mut lobes {
+            *lobe /= sum;
+        }
+        lobes
+    }
+}
+
+/// 折射率，直接给出数值时与波长无关
+#[derive(Copy, Clone, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Ior {
+    Constant(FloatT),
+    Dispersive(Dispersion),
+}
+
+/// 色散公式，波长以微米为单位
+#[derive(Copy, Clone, Deserialize, Debug)]
+pub enum Dispersion {
+    /// n = a + b / λ^2
+    Cauchy(FloatT, FloatT),
+    /// n^2 = 1 + Σ b_i λ^2 / (λ^2 - c_i)
+    Sellmeier([FloatT; 3], [FloatT; 3]),
+}
+
+impl Ior {
+    /// 波长为 wavelength（纳米）时的折射率
+    pub fn at(&self, wavelength: FloatT) -> FloatT {
+        let l2 = (wavelength / 1000.0) * (wavelength / 1000.0);
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Dispersive(Dispersion::Cauchy(a, b)) => a + b / l2,
+            Ior::Dispersive(Dispersion::Sellmeier(b, c)) => {
+                (1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<FloatT>()).sqrt()
+            }
+        }
+    }
+
+    /// 不区分波长时使用的折射率，取钠黄光 D 线处的值
+    pub fn nominal(&self) -> FloatT {
+        self.at(589.3)
+    }
+}
+
+/// 常见金属，复折射率取 RGB 三个通道（约 650, 550, 450 纳米）处的值
+#[derive(Copy, Clone, Deserialize, Debug)]
+pub enum Metal {
+    Gold,
+    Copper,
+    Silver,
+    Aluminium,
+}
+
+/// 导体的复折射率 eta + i k，各通道分别给出，外侧视为空气
+#[derive(Copy, Clone, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ComplexIor {
+    Preset(Metal),
+    Custom { eta: Color, k: Color },
+}
+
+impl ComplexIor {
+    /// 复折射率的实部和虚部
+    pub fn eta_k(&self) -> (Color, Color) {
+        match self {
+            ComplexIor::Preset(metal) => {
+                let (eta, k) = match metal {
+                    Metal::Gold => ([0.143, 0.374, 1.442], [3.983, 2.385, 1.603]),
+                    Metal::Copper => ([0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+                    Metal::Silver => ([0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+                    Metal::Aluminium => ([1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+                };
+                (Color::new(eta), Color::new(k))
+            }
+            ComplexIor::Custom { eta, k } => (*eta, *k),
+        }
+    }
+
+    /// 入射角余弦为 cos 时的 Fresnel 反射率，取 s 偏振和 p 偏振的平均
+    pub fn fresnel(&self, cos: FloatT) -> Color {
+        let (eta, k) = self.eta_k();
+        let cos = cos.abs().min(1.0);
+        let cos2 = cos * cos;
+        let sin2 = 1.0 - cos2;
+        let mut r = Color::empty();
+        for i in 0..3 {
+            let (eta2, k2) = (eta[i] * eta[i], k[i] * k[i]);
+            let t0 = eta2 - k2 - sin2;
+            let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+            let t1 = a2_plus_b2 + cos2;
+            let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+            let t2 = 2.0 * cos * a;
+            let rs = (t1 - t2) / (t1 + t2);
+            let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+            let t4 = t2 * sin2;
+            let rp = rs * (t3 - t4) / (t3 + t4);
+            r[i] = 0.5 * (rs + rp);
+        }
+        r
+    }
+}
+
+fn default_absorption() -> Color {
+    Color::empty()
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Clone, Deserialize, Debug)]
 pub struct Material {
     pub texture: Texture,
     pub surface: Surface,
+    /// 透射物体内部的吸收系数，光线在内部走过距离 d 后衰减为 exp(-absorption * d)
+    #[serde(default = "default_absorption")]
+    pub absorption: Color,
+    /// 电介质和导体表面的薄膜涂层
+    #[serde(default)]
+    pub film: Option<ThinFilm>,
 }
@@ -0,0 +1,313 @@
+use std::fmt::{Debug, Formatter};
+use std::fs;
+
+use rand::Rng;
+use serde::{Deserialize, Deserializer};
+
+use crate::graphics::{Bounding, Color};
+use crate::math::matrix::Matrix3;
+use crate::math::vector::Vector3f;
+use crate::math::{FloatT, Ray, PI};
+
+/// 参与介质，给出 grid 时为非均匀介质，各系数再乘上网格中的密度
+#[derive(Deserialize, Debug)]
+pub struct Medium {
+    /// 吸收系数
+    pub absorption: Color,
+    /// 散射系数
+    pub scattering: Color,
+    /// Henyey-Greenstein 相函数的不对称参数，正值前向散射，负值后向散射
+    #[serde(default)]
+    pub g: FloatT,
+    /// 密度网格
+    #[serde(default)]
+    pub grid: Option<Grid>,
+}
+
+/// 放在包围盒中的体素密度网格，网格外密度为零
+pub struct Grid {
+    size: [usize; 3],
+    /// x 变化最快，其次为 y、z
+    density: Vec<FloatT>,
+    bounding: Bounding,
+    /// 最大密度，作为 delta tracking 的上界
+    max: FloatT,
+}
+
+impl Debug for Grid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Grid({}, {}, {})",
+            self.size[0], self.size[1], self.size[2]
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for Grid {
+    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        fn default_scale() -> FloatT {
+            1.0
+        }
+
+        #[derive(Deserialize)]
+        struct GridInfo {
+            path: String,
+            /// 包围盒的两个角
+            min: Vector3f,
+            max: Vector3f,
+            /// 密度缩放
+            #[serde(default = "default_scale")]
+            scale: FloatT,
+        }
+
+        let info = GridInfo::deserialize(deserializer)?;
+        Ok(Grid::load(
+            &info.path,
+            Bounding {
+                min: info.min,
+                max: info.max,
+            },
+            info.scale,
+        ))
+    }
+}
+
+impl Grid {
+    /// 读取体素文件，.raw 为二进制格式：三个小端 u32 表示 x, y, z 方向的尺寸，之后为小端 f32 的密度；
+    /// 其余按文本格式读取：三个整数表示尺寸，之后为空白分隔的密度
+    pub fn load(path: &str, bounding: Bounding, scale: FloatT) -> Self {
+        let (size, density) = if path.to_lowercase().ends_with(".raw") {
+            let bytes = fs::read(path).expect(&format!("cannot read {}", path));
+            let word = |i: usize| {
+                let mut buf = [0; 4];
+                buf.copy_from_slice(&bytes[4 * i..4 * i + 4]);
+                buf
+            };
+            assert!(bytes.len() >= 12, "{} is too short for a grid header", path);
+            let mut size = [0; 3];
+            for i in 0..3 {
+                size[i] = u32::from_le_bytes(word(i)) as usize;
+            }
+            let expected = 4 * (3 + size[0] * size[1] * size[2]);
+            assert!(
+                bytes.len() >= expected,
+                "{} is truncated: expected {} bytes for a {}x{}x{} grid, got {}",
+                path,
+                expected,
+                size[0],
+                size[1],
+                size[2],
+                bytes.len()
+            );
+            let density = (0..size[0] * size[1] * size[2])
+                .map(|i| f32::from_le_bytes(word(3 + i)) as FloatT)
+                .collect::<Vec<_>>();
+            (size, density)
+        } else {
+            let text = fs::read_to_string(path).expect(&format!("cannot read {}", path));
+            let mut tokens = text.split_whitespace();
+            let mut size = [0; 3];
+            for i in 0..3 {
+                size[i] = tokens
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .expect("bad grid size");
+            }
+            let density = tokens
+                .map(|s| s.parse::<FloatT>().expect("bad grid density"))
+                .collect::<Vec<_>>();
+            (size, density)
+        };
+        assert_eq!(
+            density.len(),
+            size[0] * size[1] * size[2],
+            "bad grid: {}",
+            path
+        );
+        let density = density.iter().map(|d| d * scale).collect::<Vec<_>>();
+        let max = density.iter().cloned().fold(0.0, FloatT::max);
+        Grid {
+            size,
+            density,
+            bounding,
+            max,
+        }
+    }
+
+    fn at(&self, x: isize, y: isize, z: isize) -> FloatT {
+        let [nx, ny, nz] = self.size;
+        if x < 0 || y < 0 || z < 0 || x >= nx as isize || y >= ny as isize || z >= nz as isize {
+            0.0
+        } else {
+            self.density[(z as usize * ny + y as usize) * nx + x as usize]
+        }
+    }
+
+    /// pos 处三线性插值的密度，体素值位于各格子中心
+    pub fn density(&self, pos: &Vector3f) -> FloatT {
+        let p = (*pos - self.bounding.min) / (self.bounding.max - self.bounding.min);
+        let mut base = [0; 3];
+        let mut frac = [0.0; 3];
+        for i in 0..3 {
+            if p[i] < 0.0 || p[i] > 1.0 {
+                return 0.0;
+            }
+            let g = p[i] * self.size[i] as FloatT - 0.5;
+            base[i] = g.floor() as isize;
+            frac[i] = g - g.floor();
+        }
+        let mut ret = 0.0;
+        for dx in 0..2 {
+            for dy in 0..2 {
+                for dz in 0..2 {
+                    let w = |d: isize, i: usize| if d == 0 { 1.0 - frac[i] } else { frac[i] };
+                    ret += w(dx, 0)
+                        * w(dy, 1)
+                        * w(dz, 2)
+                        * self.at(base[0] + dx, base[1] + dy, base[2] + dz);
+                }
+            }
+        }
+        ret
+    }
+}
+
+impl Medium {
+    /// 消光系数（非均匀介质中为密度为 1 处的值）
+    pub fn extinction(&self) -> Color {
+        self.absorption + self.scattering
+    }
+
+    /// 非均匀介质中光线上与网格相交的区间，与 [0, t_max] 取交集
+    fn range(grid: &Grid, ray: &Ray, t_max: FloatT) -> Option<(FloatT, FloatT)> {
+        match grid.bounding.intersect(ray) {
+            Some((l, r)) if l < t_max => Some((l, r.min(t_max))),
+            _ => None,
+        }
+    }
+
+    /// 沿 ray 穿过距离 t 后的透射率，t 可以为无穷大；非均匀介质用 ratio tracking 无偏估计
+    pub fn transmittance<R: Rng>(&self, ray: &Ray, t: FloatT, rng: &mut R) -> Color {
+        let sigma = self.extinction();
+        match &self.grid {
+            None => {
+                let tr = |sigma: FloatT| if sigma > 0.0 { (-sigma * t).exp() } else { 1.0 };
+                Color::new([tr(sigma[0]), tr(sigma[1]), tr(sigma[2])])
+            }
+            Some(grid) => {
+                let mut tr = Color::ones();
+                let majorant = sigma[0].max(sigma[1]).max(sigma[2]) * grid.max;
+                if let (Some((l, r)), true) = (Medium::range(grid, ray, t), majorant > 0.0) {
+                    let mut t = l;
+                    loop {
+                        let u: FloatT = rng.gen_range(0.0, 1.0);
+                        t -= (1.0 - u).ln() / majorant;
+                        if t >= r {
+                            break;
+                        }
+                        let density = grid.density(&ray.at(t));
+                        tr *= Color::ones() - sigma * (density / majorant);
+                    }
+                }
+                tr
+            }
+        }
+    }
+
+    /// 透射率降到 1e-3 以下的距离，之后的贡献可以忽略；非均匀介质为光线离开网格的距离
+    pub fn cutoff(&self, ray: &Ray) -> FloatT {
+        match &self.grid {
+            None => {
+                let sigma = self.extinction();
+                let min = (0..3)
+                    .map(|i| sigma[i])
+                    .filter(|&sigma| sigma > 0.0)
+                    .fold(FloatT::INFINITY, FloatT::min);
+                (1000.0 as FloatT).ln() / min
+            }
+            Some(grid) => grid.bounding.intersect(ray).map_or(0.0, |(_, r)| r),
+        }
+    }
+
+    /// 自由程采样，在 t_max 之前发生散射时返回 (Some(t), 权重)，否则返回 (None, 权重)
+    /// 均匀介质中随机选一个通道按其消光系数采样距离，概率密度取各通道的平均，
+    /// 散射时权重为 透射率 * 散射系数 / pdf，否则为 透射率 / 概率；
+    /// 非均匀介质用 delta tracking，按各通道平均的消光系数决定真实碰撞的概率，权重补偿通道间的差异
+    pub fn sample<R: Rng>(&self, ray: &Ray, t_max: FloatT, rng: &mut R) -> (Option<FloatT>, Color) {
+        let sigma = self.extinction();
+        match &self.grid {
+            None => {
+                let channel = rng.gen_range(0, 3);
+                let u: FloatT = rng.gen_range(0.0, 1.0);
+                let t = -(1.0 - u).ln() / sigma[channel];
+                if t < t_max {
+                    let tr = self.transmittance(ray, t, rng);
+                    let pdf = (sigma * tr).norm1() / 3.0;
+                    (Some(t), tr * self.scattering / pdf)
+                } else {
+                    let tr = self.transmittance(ray, t_max, rng);
+                    let p = tr.norm1() / 3.0;
+                    (None, if p > 0.0 { tr / p } else { Color::empty() })
+                }
+            }
+            Some(grid) => {
+                let mut weight = Color::ones();
+                let majorant = sigma[0].max(sigma[1]).max(sigma[2]) * grid.max;
+                if let (Some((l, r)), true) = (Medium::range(grid, ray, t_max), majorant > 0.0) {
+                    let mut t = l;
+                    loop {
+                        let u: FloatT = rng.gen_range(0.0, 1.0);
+                        t -= (1.0 - u).ln() / majorant;
+                        if t >= r {
+                            break;
+                        }
+                        let density = grid.density(&ray.at(t));
+                        let p = sigma.norm1() / 3.0 * density / majorant;
+                        if rng.gen_range(0.0, 1.0) < p {
+                            return (
+                                Some(t),
+                                weight * self.scattering * (density / (majorant * p)),
+                            );
+                        }
+                        // 虚碰撞
+                        weight *=
+                            (Color::full(majorant) - sigma * density) / (majorant * (1.0 - p));
+                    }
+                }
+                (None, weight)
+            }
+        }
+    }
+
+    /// 光线从 wi 方向传播，散射到 wo 方向的相函数值，也是按 sample_phase 采样到 wo 的概率密度
+    pub fn phase(&self, wi: &Vector3f, wo: &Vector3f) -> FloatT {
+        let g = self.g;
+        let cos = Vector3f::dot(wi, wo);
+        let denom = 1.0 + g * g - 2.0 * g * cos;
+        (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+    }
+
+    /// 按相函数采样光线沿 wi 传播散射后的方向
+    pub fn sample_phase<R: Rng>(&self, wi: &Vector3f, rng: &mut R) -> Vector3f {
+        let g = self.g;
+        let u: FloatT = rng.gen_range(0.0, 1.0);
+        let cos = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            (1.0 + g * g - s * s) / (2.0 * g)
+        }
+        .max(-1.0)
+        .min(1.0);
+        let sin = (1.0 - cos * cos).sqrt();
+        let theta = rng.gen_range(0.0, 2.0 * PI);
+        let x = wi.get_orthogonal().normalized();
+        let y = Vector3f::cross(wi, &x);
+        Matrix3::from_vectors([x, y, *wi], true)
+            * Vector3f::new([sin * theta.cos(), sin * theta.sin(), cos])
+    }
+}
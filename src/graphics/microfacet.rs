@@ -0,0 +1,278 @@
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::math::vector::Vector3f;
+use crate::math::{FloatT, PI};
+
+/// 微表面法线分布
+#[derive(Copy, Clone, Deserialize, Debug)]
+pub enum Distribution {
+    GGX,
+    Beckmann,
+}
+
+impl Default for Distribution {
+    fn default() -> Self {
+        Distribution::GGX
+    }
+}
+
+/// 各向同性的微表面模型，方向都在以宏观法向量为 z 轴的局部坐标系中计算
+#[derive(Copy, Clone, Debug)]
+pub struct Microfacet {
+    distribution: Distribution,
+    alpha: FloatT,
+}
+
+// 以 normal 为 z 轴的局部坐标系
+fn frame(normal: &Vector3f) -> (Vector3f, Vector3f) {
+    let x = normal.get_orthogonal().normalized();
+    let y = Vector3f::cross(normal, &x);
+    (x, y)
+}
+
+fn erf(x: FloatT) -> FloatT {
+    let (a1, a2, a3, a4, a5, p) = (
+        0.254829592,
+        -0.284496736,
+        1.421413741,
+        -1.453152027,
+        1.061405429,
+        0.3275911,
+    );
+    let sign = x.signum();
+    let x = x.abs();
+    let t = 1.0 / (1.0 + p * x);
+    let y = 1.0 - (((((a5 * t + a4) * t) + a3) * t + a2) * t + a1) * t * (-x * x).exp();
+    sign * y
+}
+
+fn erf_inv(x: FloatT) -> FloatT {
+    let x = x.max(-0.99999).min(0.99999);
+    let mut w = -((1.0 - x) * (1.0 + x)).ln();
+    let p = if w < 5.0 {
+        w -= 2.5;
+        [
+            3.43273939e-07,
+            -3.5233877e-06,
+            -4.39150654e-06,
+            0.00021858087,
+            -0.00125372503,
+            -0.00417768164,
+            0.246640727,
+            1.50140941,
+        ]
+        .iter()
+        .fold(2.81022636e-08, |p, c| c + p * w)
+    } else {
+        w = w.sqrt() - 3.0;
+        [
+            0.000100950558,
+            0.00134934322,
+            -0.00367342844,
+            0.00573950773,
+            -0.0076224613,
+            0.00943887047,
+            1.00167406,
+            2.83297682,
+        ]
+        .iter()
+        .fold(-0.000200214257, |p, c| c + p * w)
+    };
+    p * x
+}
+
+impl Microfacet {
+    /// roughness 为感知上线性的粗糙度，alpha 取其平方
+    pub fn new(distribution: Distribution, roughness: FloatT) -> Self {
+        Microfacet {
+            distribution,
+            alpha: (roughness * roughness).max(1e-4),
+        }
+    }
+
+    /// 法线分布函数，cos 为微表面法向量与宏观法向量夹角的余弦
+    pub fn d(&self, cos: FloatT) -> FloatT {
+        if cos <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        let cos2 = cos * cos;
+        let tan2 = (1.0 - cos2) / cos2;
+        match self.distribution {
+            Distribution::GGX => a2 / (PI * cos2 * cos2 * (a2 + tan2) * (a2 + tan2)),
+            Distribution::Beckmann => (-tan2 / a2).exp() / (PI * a2 * cos2 * cos2),
+        }
+    }
+
+    /// Smith 遮蔽函数中的 Λ
+    fn lambda(&self, cos: FloatT) -> FloatT {
+        let cos = cos.abs();
+        if cos >= 1.0 {
+            return 0.0;
+        }
+        let tan = (1.0 - cos * cos).sqrt() / cos;
+        match self.distribution {
+            Distribution::GGX => (-1.0 + (1.0 + self.alpha * self.alpha * tan * tan).sqrt()) / 2.0,
+            Distribution::Beckmann => {
+                let a = 1.0 / (self.alpha * tan);
+                if a >= 1.6 {
+                    0.0
+                } else {
+                    (1.0 - 1.259 * a + 0.396 * a * a) / (3.535 * a + 2.181 * a * a)
+                }
+            }
+        }
+    }
+
+    fn g1(&self, cos: FloatT) -> FloatT {
+        1.0 / (1.0 + self.lambda(cos))
+    }
+
+    fn g(&self, cos_o: FloatT, cos_i: FloatT) -> FloatT {
+        1.0 / (1.0 + self.lambda(cos_o) + self.lambda(cos_i))
+    }
+
+    /// 在局部坐标系中对方向 wo（z > 0）按可见法线分布采样微表面法向量
+    fn sample_local<R: Rng>(&self, wo: Vector3f, rng: &mut R) -> Vector3f {
+        let alpha = self.alpha;
+        let u1: FloatT = rng.gen_range(0.0, 1.0);
+        let u2: FloatT = rng.gen_range(0.0, 1.0);
+        // 拉伸到 alpha = 1 的情形
+        let v = Vector3f::new([alpha * wo.x(), alpha * wo.y(), wo.z()]).normalized();
+        match self.distribution {
+            Distribution::GGX => {
+                let len2 = v.x() * v.x() + v.y() * v.y();
+                let t1 = if len2 > 0.0 {
+                    Vector3f::new([-v.y(), v.x(), 0.0]) / len2.sqrt()
+                } else {
+                    Vector3f::new([1.0, 0.0, 0.0])
+                };
+                let t2 = Vector3f::cross(&v, &t1);
+                let r = u1.sqrt();
+                let phi = 2.0 * PI * u2;
+                let p1 = r * phi.cos();
+                let s = 0.5 * (1.0 + v.z());
+                let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+                let n = t1 * p1 + t2 * p2 + v * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+                Vector3f::new([alpha * n.x(), alpha * n.y(), n.z().max(0.0)]).normalized()
+            }
+            Distribution::Beckmann => {
+                let cos = v.z();
+                let (slope_x, slope_y) = if cos > 0.9999 {
+                    let r = (-(1.0 - u1).ln()).sqrt();
+                    let phi = 2.0 * PI * u2;
+                    (r * phi.cos(), r * phi.sin())
+                } else {
+                    // 求解可见斜率分布的逆，牛顿法与二分结合
+                    let tan = (1.0 - cos * cos).max(0.0).sqrt() / cos;
+                    let cot = 1.0 / tan;
+                    let (mut a, mut c) = (-1.0, erf(cot));
+                    let u1 = u1.max(1e-6);
+                    let theta = cos.acos();
+                    let fit = 1.0 + theta * (-0.876 + theta * (0.4265 - 0.0594 * theta));
+                    let mut b = c - (1.0 + c) * (1.0 - u1).powf(fit);
+                    let normalization = 1.0 / (1.0 + c + tan * (-cot * cot).exp() / PI.sqrt());
+                    for _ in 0..10 {
+                        if !(b >= a && b <= c) {
+                            b = 0.5 * (a + c);
+                        }
+                        let inv = erf_inv(b);
+                        let value =
+                            normalization * (1.0 + b + tan * (-inv * inv).exp() / PI.sqrt()) - u1;
+                        if value.abs() < 1e-5 {
+                            break;
+                        }
+                        if value > 0.0 {
+                            c = b;
+                        } else {
+                            a = b;
+                        }
+                        b -= value / (normalization * (1.0 - inv * tan));
+                    }
+                    (erf_inv(b), erf_inv(2.0 * u2.max(1e-6) - 1.0))
+                };
+                // 旋转到 v 的方位角，再压缩回原来的 alpha
+                let len = (v.x() * v.x() + v.y() * v.y()).sqrt();
+                let (cos_phi, sin_phi) = if len > 0.0 {
+                    (v.x() / len, v.y() / len)
+                } else {
+                    (1.0, 0.0)
+                };
+                let x = cos_phi * slope_x - sin_phi * slope_y;
+                let y = sin_phi * slope_x + cos_phi * slope_y;
+                Vector3f::new([-alpha * x, -alpha * y, 1.0]).normalized()
+            }
+        }
+    }
+
+    /// 按可见法线分布采样微表面法向量，normal 为朝向 wo 一侧的宏观法向量，wo 指向观察者
+    pub fn sample<R: Rng>(&self, normal: &Vector3f, wo: &Vector3f, rng: &mut R) -> Vector3f {
+        let (x, y) = frame(normal);
+        let h = self.sample_local(
+            Vector3f::new([
+                Vector3f::dot(wo, &x),
+                Vector3f::dot(wo, &y),
+                Vector3f::dot(wo, normal),
+            ]),
+            rng,
+        );
+        x * h.x() + y * h.y() + *normal * h.z()
+    }
+
+    /// 反射时的 BSDF 值（不含 Fresnel 项）以及按 sample 采样到 wi 的概率密度（立体角测度）
+    /// wo 与 wi 不在 normal 同一侧时返回 None
+    pub fn reflect(
+        &self,
+        normal: &Vector3f,
+        wo: &Vector3f,
+        wi: &Vector3f,
+    ) -> Option<(FloatT, FloatT)> {
+        let cos_o = Vector3f::dot(normal, wo);
+        let cos_i = Vector3f::dot(normal, wi);
+        if cos_o <= 0.0 || cos_i <= 0.0 {
+            return None;
+        }
+        let h = (*wo + *wi).normalized();
+        let d = self.d(Vector3f::dot(normal, &h));
+        let f = d * self.g(cos_o, cos_i) / (4.0 * cos_o * cos_i);
+        // 可见法线分布的概率密度为 G1 * D * (wo·h) / cos_o，再乘上反射的雅可比 1 / (4 wo·h)
+        let pdf = self.g1(cos_o) * d / (4.0 * cos_o);
+        Some((f, pdf))
+    }
+
+    /// 从折射率 n 的一侧折射到 nt 的一侧时的微表面法向量、BSDF 值（不含 Fresnel 项）和概率密度
+    /// BSDF 取 n * nt 作为折射率因子，使其对 wo 和 wi 对称，双向方法中两个方向的求值一致
+    /// wi 与 wo 在 normal 同一侧或不能由折射得到时返回 None
+    pub fn transmit(
+        &self,
+        normal: &Vector3f,
+        wo: &Vector3f,
+        wi: &Vector3f,
+        n: FloatT,
+        nt: FloatT,
+    ) -> Option<(Vector3f, FloatT, FloatT)> {
+        let cos_o = Vector3f::dot(normal, wo);
+        let cos_i = Vector3f::dot(normal, wi);
+        let h = -(*wo * n + *wi * nt);
+        if cos_o <= 0.0 || cos_i >= 0.0 || h.length2() == 0.0 {
+            return None;
+        }
+        let h = h.normalized();
+        let h = if Vector3f::dot(normal, &h) < 0.0 {
+            -h
+        } else {
+            h
+        };
+        let (cos_oh, cos_ih) = (Vector3f::dot(wo, &h), Vector3f::dot(wi, &h));
+        if cos_oh <= 0.0 || cos_ih >= 0.0 {
+            return None;
+        }
+        let denom = (n * cos_oh + nt * cos_ih) * (n * cos_oh + nt * cos_ih);
+        let d = self.d(Vector3f::dot(normal, &h));
+        let f = d * self.g(cos_o, cos_i) * cos_oh * -cos_ih * n * nt / (cos_o * -cos_i * denom);
+        // 折射的雅可比为 nt^2 |wi·h| / denom
+        let pdf = self.g1(cos_o) * d * cos_oh / cos_o * nt * nt * -cos_ih / denom;
+        Some((h, f, pdf))
+    }
+}
@@ -1,19 +1,31 @@
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 
-use crate::graphics::material::{Material, Texture};
-use crate::graphics::shape::{RandOut, Shape};
+use crate::graphics::material::{Material, Surface, Texture};
+use crate::graphics::medium::Medium;
+use crate::graphics::shape::{rand_cos_semisphere, RandOut, RandPoint, Shape};
 use crate::math::vector::{Vector2f, Vector3f};
-use crate::math::{FloatT, Ray};
+use crate::math::{FloatT, Ray, PI};
 use rand::prelude::ThreadRng;
+use rand::Rng;
 
 mod bounding;
+pub mod bsdf;
+pub mod film;
 pub mod material;
+pub mod medium;
+pub mod microfacet;
 pub mod shape;
+pub mod spectrum;
 
 pub use bounding::*;
 
 pub type Color = Vector3f;
 
+/// 颜色的亮度，按 Rec. 709 的权重
+pub fn luminance(color: &Color) -> FloatT {
+    0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2]
+}
+
 pub struct HitTemp {
     pub t: FloatT,
     pub normal: Vector3f,
@@ -44,12 +56,99 @@ trait TextureMap {
     ) -> (usize, usize);
 }
 
+fn default_scale() -> FloatT {
+    1.0
+}
+
+/// 物体的发光，可以是常量，也可以是乘上 scale 的纹理
 #[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Emission {
+    Constant(Color),
+    Textured {
+        texture: Texture,
+        #[serde(default = "default_scale")]
+        scale: FloatT,
+    },
+}
+
+/// 发光的一侧，正面为形状法向量所指的一侧
+#[derive(Copy, Clone, Deserialize, Debug)]
+pub enum EmissionSide {
+    Front,
+    Back,
+    Both,
+}
+
+impl Default for EmissionSide {
+    fn default() -> Self {
+        EmissionSide::Both
+    }
+}
+
+/// 发光纹理
+#[derive(Debug)]
+struct EmissionTexture {
+    texture: Texture,
+    scale: FloatT,
+    /// 纹素亮度的上界，用于按亮度采样光子的发射位置
+    max_luminance: FloatT,
+}
+
+#[derive(Debug)]
 pub struct Object {
     shape: Shape,
     pub material: Material,
-    /// 物体自身发光
+    /// 物体自身发光，有发光纹理时为纹理的平均值，各点的发光见 `flux_at`
     pub flux: Color,
+    emission: Option<EmissionTexture>,
+    pub emission_side: EmissionSide,
+    /// 物体内部的介质，只对封闭的折射物体有效
+    pub medium: Option<Medium>,
+}
+
+impl<'de> Deserialize<'de> for Object {
+    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct ObjectInfo {
+            shape: Shape,
+            material: Material,
+            flux: Emission,
+            #[serde(default)]
+            emission_side: EmissionSide,
+            #[serde(default)]
+            medium: Option<Medium>,
+        }
+
+        let info = ObjectInfo::deserialize(deserializer)?;
+        // 次表面散射材质没有给出介质时，由材质参数得到内部的介质
+        let medium = match (&info.material.surface, info.medium) {
+            (Surface::Subsurface(subsurface), None) => Some(subsurface.medium()),
+            (_, medium) => medium,
+        };
+        let (flux, emission) = match info.flux {
+            Emission::Constant(flux) => (flux, None),
+            Emission::Textured { texture, scale } => (
+                texture.average() * scale,
+                Some(EmissionTexture {
+                    max_luminance: texture.max_luminance() * scale,
+                    texture,
+                    scale,
+                }),
+            ),
+        };
+        Ok(Object {
+            shape: info.shape,
+            material: info.material,
+            flux,
+            emission,
+            emission_side: info.emission_side,
+            medium,
+        })
+    }
 }
 
 impl RandOut for Object {
@@ -58,6 +157,16 @@ impl RandOut for Object {
     }
 }
 
+impl RandPoint for Object {
+    fn rand_point<R: Rng>(&self, rng: &mut R) -> (Vector3f, Vector3f, Option<(FloatT, FloatT)>) {
+        self.shape.rand_point(rng)
+    }
+
+    fn area(&self) -> FloatT {
+        self.shape.area()
+    }
+}
+
 impl Hittable for Object {
     fn hit(&self, r: &Ray, t_min: f64) -> Option<HitTemp> {
         self.shape.hit(r, t_min)
@@ -65,6 +174,18 @@ impl Hittable for Object {
 }
 
 impl Object {
+    /// 自发光为常量、两面发光且内部没有介质的物体
+    pub fn new(shape: Shape, material: Material, flux: Color) -> Self {
+        Object {
+            shape,
+            material,
+            flux,
+            emission: None,
+            emission_side: EmissionSide::Both,
+            medium: None,
+        }
+    }
+
     pub fn make_hit(&self, pos: Vector3f, normal: Vector3f, uv: Option<(FloatT, FloatT)>) -> Hit {
         Hit {
             pos,
@@ -74,8 +195,46 @@ impl Object {
         }
     }
 
-    pub fn color_at(&self, pos: Vector3f, uv: Option<(FloatT, FloatT)>) -> Color {
-        match &self.material.texture {
+    /// 读取了材质库的网格按材质拆成多个物体，没有指定材质的部分沿用该物体的材质和自发光
+    /// 其余物体原样返回
+    pub fn split(mut self) -> Vec<Object> {
+        let parts = match &mut self.shape {
+            Shape::Mesh(mesh) if !mesh.parts.is_empty() => std::mem::take(&mut mesh.parts),
+            _ => return vec![self],
+        };
+        let emission_side = self.emission_side;
+        let mut own = Some(self);
+        parts
+            .into_iter()
+            .filter_map(|part| {
+                let shape = Shape::Mesh(part.mesh);
+                match part.material {
+                    Some((material, flux)) => Some(Object {
+                        shape,
+                        material,
+                        flux,
+                        emission: None,
+                        emission_side,
+                        medium: None,
+                    }),
+                    None => own.take().map(|object| Object { shape, ..object }),
+                }
+            })
+            .collect()
+    }
+
+    /// 能否作为光源被直接采样
+    pub fn is_light(&self) -> bool {
+        self.flux.norm1() > 0.0 && self.shape.samplable()
+    }
+
+    /// 对该物体按面积采样时对应的立体角概率密度，见 `Shape::pdf`
+    pub fn pdf(&self, point: &Vector3f, direction: &Vector3f) -> FloatT {
+        self.shape.pdf(point, direction)
+    }
+
+    fn texture_at(&self, texture: &Texture, pos: Vector3f, uv: Option<(FloatT, FloatT)>) -> Color {
+        match texture {
             Texture::Pure(color) => *color,
             Texture::Image(image) => {
                 let (x, y) = self.shape.texture_map(pos, uv, image.w, image.h);
@@ -83,4 +242,93 @@ impl Object {
             }
         }
     }
+
+    pub fn color_at(&self, pos: Vector3f, uv: Option<(FloatT, FloatT)>) -> Color {
+        self.texture_at(&self.material.texture, pos, uv)
+    }
+
+    /// pos 处的自发光，uv 为 hit 或 rand_point 给出的纹理坐标
+    pub fn flux_at(&self, pos: Vector3f, uv: Option<(FloatT, FloatT)>) -> Color {
+        match &self.emission {
+            Some(emission) => self.texture_at(&emission.texture, pos, uv) * emission.scale,
+            None => self.flux,
+        }
+    }
+
+    /// 法向量为 normal 的点是否朝 w 方向发光
+    pub fn emits(&self, normal: &Vector3f, w: &Vector3f) -> bool {
+        let cos = Vector3f::dot(normal, w);
+        match self.emission_side {
+            EmissionSide::Front => cos > 0.0,
+            EmissionSide::Back => cos < 0.0,
+            EmissionSide::Both => true,
+        }
+    }
+
+    /// pos 处朝 w 方向的自发光，normal 为该处形状的法向量
+    pub fn flux_toward(
+        &self,
+        pos: Vector3f,
+        uv: Option<(FloatT, FloatT)>,
+        normal: &Vector3f,
+        w: &Vector3f,
+    ) -> Color {
+        if self.emits(normal, w) {
+            self.flux_at(pos, uv)
+        } else {
+            Color::empty()
+        }
+    }
+
+    /// 在发光的一侧按余弦加权采样发射方向，两面发光时随机选择一侧
+    pub fn emit_dir<R: Rng>(&self, normal: &Vector3f, rng: &mut R) -> Vector3f {
+        let front = match self.emission_side {
+            EmissionSide::Front => true,
+            EmissionSide::Back => false,
+            EmissionSide::Both => rng.gen_range(0.0, 1.0) < 0.5,
+        };
+        rand_cos_semisphere(&if front { *normal } else { -*normal }, rng)
+    }
+
+    /// 按 `emit_dir` 采样到 w 方向的概率密度（立体角测度）
+    pub fn emit_pdf(&self, normal: &Vector3f, w: &Vector3f) -> FloatT {
+        if !self.emits(normal, w) {
+            return 0.0;
+        }
+        let sides = match self.emission_side {
+            EmissionSide::Both => 2.0,
+            _ => 1.0,
+        };
+        Vector3f::dot(normal, w).abs() / (sides * PI)
+    }
+
+    /// 发射一个光子，返回光线及光子携带的能量（按物体的总能量计）
+    /// 有发光纹理时用拒绝采样使发射位置的分布正比于纹素亮度
+    pub fn emit(&self, rng: &mut ThreadRng) -> (Ray, Color) {
+        loop {
+            let (ray, flux) = if self.shape.samplable() {
+                let (pos, normal, uv) = self.rand_point(rng);
+                let ray = Ray::new(pos, self.emit_dir(&normal, rng));
+                (ray, self.flux_at(pos, uv))
+            } else {
+                // 无法按面积采样的形状中只有无限平面能发射光子，位置沿用 rand_out，
+                // 方向与其他光源一样只在发光的一侧采样
+                let (pos, normal) = match &self.shape {
+                    Shape::Plane(plane) => (plane.rand_out(rng).origin, plane.normal),
+                    _ => panic!("cannot emit photons from a bezier surface"),
+                };
+                let ray = Ray::new(pos, self.emit_dir(&normal, rng));
+                (ray, self.flux_at(pos, None))
+            };
+            match &self.emission {
+                Some(emission) if emission.max_luminance > 0.0 => {
+                    let l = luminance(&flux);
+                    if rng.gen_range(0.0, emission.max_luminance) < l {
+                        return (ray, flux * (luminance(&self.flux) / l));
+                    }
+                }
+                _ => return (ray, flux),
+            }
+        }
+    }
 }
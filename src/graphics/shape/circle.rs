@@ -1,4 +1,4 @@
-use crate::graphics::shape::{rand_semisphere, RandOut};
+use crate::graphics::shape::{rand_semisphere, RandOut, RandPoint};
 use crate::graphics::{HitTemp, Hittable};
 use crate::math::vector::Vector3f;
 use crate::math::{sqr, FloatT, Ray, PI};
@@ -33,7 +33,7 @@ impl<'de> Deserialize<'de> for Circle {
 
 impl Circle {
     pub fn new(origin: Vector3f, normal: Vector3f, radius: FloatT) -> Self {
-        let x = normal.get_orthogonal();
+        let x = normal.get_orthogonal().normalized();
         let y = Vector3f::cross(&normal, &x);
         Self {
             origin,
@@ -75,3 +75,17 @@ impl RandOut for Circle {
         Ray::new(pos, rand_semisphere(&self.normal, rng))
     }
 }
+
+impl RandPoint for Circle {
+    fn rand_point<R: Rng>(&self, rng: &mut R) -> (Vector3f, Vector3f, Option<(FloatT, FloatT)>) {
+        let theta = rng.gen_range(0.0, 2.0 * PI);
+        // 开方保证按面积均匀
+        let r = self.radius * rng.gen_range(0.0, 1.0 as FloatT).sqrt();
+        let pos = r * (self.x * theta.cos() + self.y * theta.sin()) + self.origin;
+        (pos, self.normal, None)
+    }
+
+    fn area(&self) -> FloatT {
+        PI * sqr(self.radius)
+    }
+}
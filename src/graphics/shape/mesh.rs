@@ -1,11 +1,17 @@
 use std::io::{BufRead, Read};
+use std::path::Path;
 
-use crate::graphics::shape::Triangle;
-use crate::graphics::{Bounding, HitTemp, Hittable};
+use crate::graphics::material::Material;
+use crate::graphics::shape::mtl::load_mtl;
+use crate::graphics::shape::ply::load_ply;
+use crate::graphics::shape::stl::load_stl;
+use crate::graphics::shape::{RandPoint, Triangle};
+use crate::graphics::{Bounding, Color, HitTemp, Hittable, TextureMap};
 use crate::math::matrix::Matrix3;
 use crate::math::vector::Vector3f;
 use crate::math::{FloatT, Ray, INF};
 use crate::utils::kdtree::triangle::Node;
+use rand::Rng;
 use serde::export::Formatter;
 use serde::{Deserialize, Deserializer};
 use std::fmt::Debug;
@@ -13,8 +19,51 @@ use std::fmt::Debug;
 pub struct Mesh {
     points: Vec<Vector3f>,
     triangles: Vec<Triangle>,
+    /// 三角形面积的前缀和，用于按面积采样
+    areas: Vec<FloatT>,
     bounding: Bounding,
-    kdtree: Box<Node>,
+    /// 只作为各部分容器的网格没有自己的三角形，见 `from_parts`
+    kdtree: Option<Box<Node>>,
+    /// 读取 MTL 材质库时按材质划分的各部分，见 `Object::split`
+    pub parts: Vec<MeshPart>,
+}
+
+/// 网格文件的格式
+#[derive(Copy, Clone, Deserialize, Debug)]
+pub enum MeshFormat {
+    Obj,
+    Ply,
+    Stl,
+}
+
+impl MeshFormat {
+    /// 由文件扩展名判断格式
+    pub fn from_path(path: &str) -> Self {
+        let ext = Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+        match ext.as_deref() {
+            Some("obj") => MeshFormat::Obj,
+            Some("ply") => MeshFormat::Ply,
+            Some("stl") => MeshFormat::Stl,
+            _ => panic!("cannot infer mesh format of {}", path),
+        }
+    }
+}
+
+/// 网格文件中的顶点
+pub struct MeshVertex {
+    pub pos: Vector3f,
+    pub normal: Option<Vector3f>,
+    pub uv: Option<(FloatT, FloatT)>,
+}
+
+/// 网格中使用同一材质的三角形
+pub struct MeshPart {
+    /// 材质及自发光，为 None 时沿用场景中给出的材质
+    pub material: Option<(Material, Color)>,
+    pub mesh: Mesh,
 }
 
 impl Debug for Mesh {
@@ -41,6 +90,15 @@ impl<'de> Deserialize<'de> for Mesh {
             shift: Vector3f,
             scale: Vector3f,
             rotates: Vec<Rotate>,
+            /// 是否读取 OBJ 引用的 MTL 材质库
+            #[serde(default)]
+            mtl: bool,
+            /// 只读取这些名字的对象或组，为空时读取全部
+            #[serde(default)]
+            names: Vec<String>,
+            /// 文件格式，不给出时由扩展名判断
+            #[serde(default)]
+            format: Option<MeshFormat>,
         }
 
         let info = MeshInfo::deserialize(deserializer)?;
@@ -60,76 +118,292 @@ impl<'de> Deserialize<'de> for Mesh {
                 }
             })
             .collect::<Vec<_>>();
-        Ok(Mesh::from_obj(&info.path, info.shift, info.scale, rotates))
+        let format = info
+            .format
+            .unwrap_or_else(|| MeshFormat::from_path(&info.path));
+        Ok(match format {
+            MeshFormat::Obj => Mesh::from_obj(
+                &info.path,
+                info.shift,
+                info.scale,
+                rotates,
+                info.mtl,
+                &info.names,
+            ),
+            MeshFormat::Ply => {
+                let (vertices, faces) = load_ply(&info.path);
+                Mesh::from_indexed(vertices, faces, info.shift, info.scale, rotates)
+            }
+            MeshFormat::Stl => {
+                let (vertices, faces) = load_stl(&info.path);
+                Mesh::from_indexed(vertices, faces, info.shift, info.scale, rotates)
+            }
+        })
     }
 }
 
+/// 先旋转，再以原包围盒中心 mid 为中心缩放，最后平移
+fn place(
+    p: Vector3f,
+    mid: Vector3f,
+    shift: Vector3f,
+    scale: Vector3f,
+    rotates: &[Matrix3],
+) -> Vector3f {
+    let p = rotates.iter().fold(p, |p, r| *r * p);
+    mid + scale * (p - mid) + shift
+}
+
+/// 顶点按 `place` 变换后对应的法向量：同样旋转，缩放部分按逆转置变换
+fn place_normal(n: Vector3f, scale: Vector3f, rotates: &[Matrix3]) -> Vector3f {
+    let n = rotates.iter().fold(n, |n, r| *r * n);
+    (n / scale).normalized()
+}
+
+/// 包围盒的中心
+fn center(points: &[Vector3f]) -> Vector3f {
+    let bounding = Bounding::build(points);
+    (bounding.min + bounding.max) / 2.0
+}
+
 impl Mesh {
-    pub fn from_obj(path: &str, shift: Vector3f, scale: Vector3f, rotates: Vec<Matrix3>) -> Self {
-        let data = std::fs::read_to_string(path).expect(&format!("cannot read from {}", path));
-        let mut object = wavefront_obj::obj::parse(data)
-            .expect(&format!("load from {} failed", path))
-            .objects
-            .pop()
-            .expect("no object");
-        let mut points = object
-            .vertices
+    /// 由顶点及三角形的顶点下标建立网格，变换方式与 `from_obj` 相同
+    pub fn from_indexed(
+        vertices: Vec<MeshVertex>,
+        faces: Vec<[usize; 3]>,
+        shift: Vector3f,
+        scale: Vector3f,
+        rotates: Vec<Matrix3>,
+    ) -> Self {
+        assert!(!faces.is_empty(), "no triangle found");
+        let mid = center(&vertices.iter().map(|v| v.pos).collect::<Vec<_>>());
+        let points = vertices
             .iter()
-            .map(|v| Vector3f::new([v.x, v.y, v.z]))
+            .map(|v| place(v.pos, mid, shift, scale, &rotates))
             .collect::<Vec<_>>();
+        let normals = vertices
+            .iter()
+            .map(|v| v.normal.map(|n| place_normal(n, scale, &rotates)))
+            .collect::<Vec<_>>();
+        let triangles = faces
+            .iter()
+            .map(|&[a, b, c]| {
+                let (va, vb, vc) = (&vertices[a], &vertices[b], &vertices[c]);
+                Triangle::new(
+                    [points[a], points[b], points[c]],
+                    match (normals[a], normals[b], normals[c]) {
+                        (Some(na), Some(nb), Some(nc)) => Some([na, nb, nc]),
+                        _ => None,
+                    },
+                    match (va.uv, vb.uv, vc.uv) {
+                        (Some(ta), Some(tb), Some(tc)) => Some([ta, tb, tc]),
+                        _ => None,
+                    },
+                )
+            })
+            .collect();
+        Mesh::from_triangles(triangles)
+    }
+
+    /// 从 OBJ 文件读取网格，mtl 为 true 时按 usemtl 将三角形划分为使用不同材质的部分
+    /// names 非空时只保留名字在其中的对象（o）或组（g），点和线没有面积，直接忽略
+    pub fn from_obj(
+        path: &str,
+        shift: Vector3f,
+        scale: Vector3f,
+        rotates: Vec<Matrix3>,
+        mtl: bool,
+        names: &[String],
+    ) -> Self {
+        let data = std::fs::read_to_string(path).expect(&format!("cannot read from {}", path));
+        let obj_set = wavefront_obj::obj::parse(data).expect(&format!("load from {} failed", path));
+        let mut materials = match (&obj_set.material_library, mtl) {
+            (Some(library), true) => {
+                let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+                load_mtl(dir.join(library).to_str().unwrap())
+            }
+            _ => Default::default(),
+        };
+        // 所有对象作为一个整体绕中心缩放
         let mid = {
-            let bounding = Bounding::build(&points);
-            (bounding.min + bounding.max) / 2.0
+            let points = obj_set
+                .objects
+                .iter()
+                .flat_map(|object| object.vertices.iter())
+                .map(|v| Vector3f::new([v.x, v.y, v.z]))
+                .collect::<Vec<_>>();
+            assert!(!points.is_empty(), "no vertex found in {}", path);
+            center(&points)
         };
-        points.iter_mut().for_each(|p| {
-            rotates.iter().for_each(|r| *p = *r * *p);
-            *p = mid + scale * (*p - mid) + shift;
-        });
-        let bounding = Bounding::build(&points);
-        let normals = object
-            .normals
+        let selected = |object: &str, groups: &[String]| {
+            names.is_empty()
+                || names
+                    .iter()
+                    .any(|name| name == object || groups.contains(name))
+        };
+
+        // 使用同一材质的各组合并为一部分，材质库中没有的材质沿用场景中给出的材质
+        let mut groups: Vec<(Option<String>, Vec<Triangle>)> = vec![];
+        for object in &obj_set.objects {
+            let points = object
+                .vertices
+                .iter()
+                .map(|v| place(Vector3f::new([v.x, v.y, v.z]), mid, shift, scale, &rotates))
+                .collect::<Vec<_>>();
+            let normals = object
+                .normals
+                .iter()
+                .map(|n| place_normal(Vector3f::new([n.x, n.y, n.z]), scale, &rotates))
+                .collect::<Vec<_>>();
+            let uvs = object
+                .tex_vertices
+                .iter()
+                .map(|t| (t.u, t.v))
+                .collect::<Vec<_>>();
+            for geometry in &object.geometry {
+                let name = geometry
+                    .material_name
+                    .clone()
+                    .filter(|name| materials.contains_key(name));
+                let triangles = geometry
+                    .shapes
+                    .iter()
+                    .filter(|s| selected(&object.name, &s.groups))
+                    .filter_map(|s| {
+                        use wavefront_obj::obj::Primitive;
+                        match s.primitive {
+                            // 多边形在解析时已按扇形剖分为三角形
+                            Primitive::Triangle((a, ta, na), (b, tb, nb), (c, tc, nc)) => {
+                                Some(Triangle::new(
+                                    [points[a], points[b], points[c]],
+                                    if na.is_some() {
+                                        Some([
+                                            normals[na.unwrap()],
+                                            normals[nb.unwrap()],
+                                            normals[nc.unwrap()],
+                                        ])
+                                    } else {
+                                        None
+                                    },
+                                    if ta.is_some() {
+                                        Some([uvs[ta.unwrap()], uvs[tb.unwrap()], uvs[tc.unwrap()]])
+                                    } else {
+                                        None
+                                    },
+                                ))
+                            }
+                            Primitive::Point(_) | Primitive::Line(_, _) => None,
+                        }
+                    });
+                match groups.iter_mut().find(|(n, _)| *n == name) {
+                    Some((_, group)) => group.extend(triangles),
+                    None => groups.push((name, triangles.collect())),
+                }
+            }
+        }
+        groups.retain(|(_, triangles)| !triangles.is_empty());
+        assert!(!groups.is_empty(), "no triangle found in {}", path);
+
+        if mtl {
+            Mesh::from_parts(
+                groups
+                    .into_iter()
+                    .map(|(name, triangles)| MeshPart {
+                        material: name.map(|name| materials.remove(&name).unwrap()),
+                        mesh: Mesh::from_triangles(triangles),
+                    })
+                    .collect(),
+            )
+        } else {
+            Mesh::from_triangles(
+                groups
+                    .into_iter()
+                    .flat_map(|(_, triangles)| triangles)
+                    .collect(),
+            )
+        }
+    }
+
+    /// 只包含各部分的网格，自身没有三角形，场景建立时由 `Object::split` 拆成各部分
+    fn from_parts(parts: Vec<MeshPart>) -> Self {
+        let points = parts
+            .iter()
+            .flat_map(|part| part.mesh.points.iter().cloned())
+            .collect::<Vec<_>>();
+        Self {
+            bounding: Bounding::build(&points),
+            points,
+            triangles: vec![],
+            areas: vec![],
+            kdtree: None,
+            parts,
+        }
+    }
+
+    pub fn from_triangles(triangles: Vec<Triangle>) -> Self {
+        let points = triangles
             .iter()
-            .map(|n| Vector3f::new([n.x, n.y, n.z]).normalized())
+            .flat_map(|t| t.vertices.iter().cloned())
             .collect::<Vec<_>>();
-        let triangles = object
-            .geometry
-            .pop()
-            .expect("no geometry found")
-            .shapes
+        let bounding = Bounding::build(&points);
+        let areas = triangles
             .iter()
-            .map(|s| {
-                use wavefront_obj::obj::Primitive;
-                match s.primitive {
-                    Primitive::Triangle((a, _, na), (b, _, nb), (c, _, nc)) => Triangle::new(
-                        [points[a], points[b], points[c]],
-                        if na.is_some() {
-                            Some([
-                                normals[na.unwrap()],
-                                normals[nb.unwrap()],
-                                normals[nc.unwrap()],
-                            ])
-                        } else {
-                            None
-                        },
-                    ),
-                    _ => panic!("unsupported"),
-                }
+            .scan(0.0, |sum, t| {
+                *sum += t.area();
+                Some(*sum)
             })
-            .collect::<Vec<_>>();
+            .collect();
 
         Self {
             points,
             bounding,
+            areas,
             triangles: triangles.clone(),
-            kdtree: Node::new(triangles),
+            kdtree: Some(Node::new(triangles)),
+            parts: vec![],
         }
     }
 }
 
+impl RandPoint for Mesh {
+    fn rand_point<R: Rng>(&self, rng: &mut R) -> (Vector3f, Vector3f, Option<(FloatT, FloatT)>) {
+        let x = rng.gen_range(0.0, self.area());
+        let i = self
+            .areas
+            .binary_search_by(|a| a.partial_cmp(&x).unwrap())
+            .unwrap_or_else(|i| i);
+        self.triangles[i.min(self.triangles.len() - 1)].rand_point(rng)
+    }
+
+    fn area(&self) -> FloatT {
+        *self.areas.last().unwrap()
+    }
+}
+
+impl TextureMap for Mesh {
+    fn texture_map(
+        &self,
+        _pos: Vector3f,
+        uv: Option<(FloatT, FloatT)>,
+        w: usize,
+        h: usize,
+    ) -> (usize, usize) {
+        // 没有纹理坐标的网格取第一个纹素
+        let (u, v) = uv.unwrap_or((0.0, 1.0));
+        // 超出 [0, 1] 的纹理坐标按重复处理，OBJ 的 v 轴朝上，图像的行从上往下
+        let u = u.rem_euclid(1.0);
+        let v = 1.0 - v.rem_euclid(1.0);
+        (
+            ((w as FloatT * u) as usize).min(w - 1),
+            ((h as FloatT * v) as usize).min(h - 1),
+        )
+    }
+}
+
 impl Hittable for Mesh {
     fn hit(&self, ray: &Ray, t_min: f64) -> Option<HitTemp> {
         if let Some((l, r)) = self.bounding.intersect(ray) {
-            if let Some(ret) = self.kdtree.hit(ray, t_min, INF) {
+            if let Some(ret) = self.kdtree.as_ref()?.hit(ray, t_min, INF) {
                 Some(ret)
             } else {
                 None
@@ -4,7 +4,7 @@ use crate::graphics::shape::rectangle::Rectangle;
 use crate::graphics::{Hit, HitTemp, Hittable, TextureMap};
 use crate::math::matrix::Matrix3;
 use crate::math::vector::Vector3f;
-use crate::math::{FloatT, Ray, PI, ZERO};
+use crate::math::{FloatT, Ray, EPS, PI, ZERO};
 pub use bezier::*;
 pub use plane::*;
 use rand::prelude::ThreadRng;
@@ -14,9 +14,12 @@ pub use sphere::*;
 mod bezier;
 mod circle;
 mod mesh;
+mod mtl;
 mod plane;
+mod ply;
 mod rectangle;
 mod sphere;
+mod stl;
 mod triangle;
 
 pub use circle::*;
@@ -47,15 +50,16 @@ impl RandOut for Shape {
     }
 }
 
-pub fn rand_sphere(rng: &mut ThreadRng) -> Vector3f {
+// 球面上均匀采样
+pub fn rand_sphere<R: Rng>(rng: &mut R) -> Vector3f {
     let theta = rng.gen_range(0.0, 2.0 * PI);
-    let phi = rng.gen_range(0.0, PI);
-    let sin_phi = phi.sin();
-    Vector3f::new([sin_phi * theta.cos(), sin_phi * theta.sin(), phi.cos()])
+    let cos_phi: FloatT = rng.gen_range(-1.0, 1.0);
+    let sin_phi = (1.0 - cos_phi * cos_phi).max(0.0).sqrt();
+    Vector3f::new([sin_phi * theta.cos(), sin_phi * theta.sin(), cos_phi])
 }
 
 // z: normal
-pub fn rand_semisphere(z: &Vector3f, rng: &mut ThreadRng) -> Vector3f {
+pub fn rand_semisphere<R: Rng>(z: &Vector3f, rng: &mut R) -> Vector3f {
     // 以 normal 为 z 轴随便建个单位正交坐标系
     let x = z.get_orthogonal();
     let y = Vector3f::cross(z, &x);
@@ -68,10 +72,82 @@ pub fn rand_semisphere(z: &Vector3f, rng: &mut ThreadRng) -> Vector3f {
         * Vector3f::new([sin_phi * theta.cos(), sin_phi * theta.sin(), phi.cos()])
 }
 
+// 按余弦加权在半球上采样，概率密度为 cos / PI
+pub fn rand_cos_semisphere<R: Rng>(z: &Vector3f, rng: &mut R) -> Vector3f {
+    let x = z.get_orthogonal().normalized();
+    let y = Vector3f::cross(z, &x);
+
+    let theta = rng.gen_range(ZERO, 2.0 * PI);
+    let r2: FloatT = rng.gen_range(ZERO, 1.0);
+    let r = r2.sqrt();
+    Matrix3::from_vectors([x, y, *z], true)
+        * Vector3f::new([r * theta.cos(), r * theta.sin(), (1.0 - r2).sqrt()])
+}
+
 pub trait RandOut {
     fn rand_out(&self, rng: &mut ThreadRng) -> Ray;
 }
 
+/// 在表面上按面积均匀采样
+pub trait RandPoint {
+    /// 返回采样点、该点的法向量及纹理坐标，纹理坐标与光线打到该点时 `hit` 给出的相同
+    fn rand_point<R: Rng>(&self, rng: &mut R) -> (Vector3f, Vector3f, Option<(FloatT, FloatT)>);
+    /// 表面积，即采样概率密度的倒数
+    fn area(&self) -> FloatT;
+}
+
+/// 只有 `samplable` 的形状才能按面积采样，场景只会把这样的发光物体放进光源列表
+impl RandPoint for Shape {
+    fn rand_point<R: Rng>(&self, rng: &mut R) -> (Vector3f, Vector3f, Option<(FloatT, FloatT)>) {
+        use Shape::*;
+        debug_assert!(self.samplable());
+        match self {
+            Sphere(sphere) => sphere.rand_point(rng),
+            Rectangle(rec) => rec.rand_point(rng),
+            Circle(circle) => circle.rand_point(rng),
+            Mesh(mesh) => mesh.rand_point(rng),
+            Plane(_) | Bezier(_) => unreachable!("cannot sample a point on an unsamplable shape"),
+        }
+    }
+
+    fn area(&self) -> FloatT {
+        use Shape::*;
+        debug_assert!(self.samplable());
+        match self {
+            Sphere(sphere) => sphere.area(),
+            Rectangle(rec) => rec.area(),
+            Circle(circle) => circle.area(),
+            Mesh(mesh) => mesh.area(),
+            Plane(_) | Bezier(_) => unreachable!("area of an unsamplable shape is not available"),
+        }
+    }
+}
+
+impl Shape {
+    /// 能否在表面上采样（无限平面和旋转曲面不行）
+    pub fn samplable(&self) -> bool {
+        match self {
+            Shape::Plane(_) | Shape::Bezier(_) => false,
+            _ => true,
+        }
+    }
+
+    /// 从 point 出发看向 direction（单位向量），在表面上按面积均匀采样时该方向在立体角测度下的概率密度
+    pub fn pdf(&self, point: &Vector3f, direction: &Vector3f) -> FloatT {
+        match self.hit(&Ray::new(*point, *direction), EPS) {
+            Some(HitTemp { t, normal, .. }) => {
+                let cos = Vector3f::dot(&normal, direction).abs();
+                if cos > 0.0 {
+                    t * t / (cos * self.area())
+                } else {
+                    0.0
+                }
+            }
+            None => 0.0,
+        }
+    }
+}
+
 impl Hittable for Shape {
     fn hit(&self, r: &Ray, t_min: FloatT) -> Option<HitTemp> {
         use Shape::*;
@@ -101,7 +177,7 @@ impl TextureMap for Shape {
             Bezier(bezier) => bezier.texture_map(pos, uv, w, h),
             Rectangle(rec) => rec.texture_map(pos, uv, w, h),
             Circle(_circle) => unimplemented!(),
-            Mesh(_) => unimplemented!(),
+            Mesh(mesh) => mesh.texture_map(pos, uv, w, h),
         }
     }
 }
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::graphics::material::{Ior, Material, Principled, Surface, Texture};
+use crate::graphics::microfacet::Distribution;
+use crate::graphics::Color;
+use crate::math::FloatT;
+use crate::utils::Image;
+
+/// MTL 文件中一个材质的参数，未给出的取 MTL 的默认值
+struct MtlInfo {
+    kd: Color,
+    ks: Color,
+    ke: Color,
+    tf: Color,
+    ns: FloatT,
+    ni: FloatT,
+    /// 不透明度
+    d: FloatT,
+    illum: u32,
+    map_kd: Option<String>,
+}
+
+impl Default for MtlInfo {
+    fn default() -> Self {
+        Self {
+            kd: Color::full(0.8),
+            ks: Color::empty(),
+            ke: Color::empty(),
+            tf: Color::ones(),
+            ns: 0.0,
+            ni: 1.0,
+            d: 1.0,
+            illum: 2,
+            map_kd: None,
+        }
+    }
+}
+
+impl MtlInfo {
+    /// 转换为本项目的材质及自发光
+    /// 透明的材质视为电介质，有镜面反射的视为光泽材质，其余为漫反射
+    fn into_material(self, dir: &Path) -> (Material, Color) {
+        // Phong 指数到微表面粗糙度的常用换算
+        let roughness = (2.0 / (self.ns + 2.0)).sqrt();
+        let texture = |color: Color| match &self.map_kd {
+            Some(path) => Texture::Image(Image::load(dir.join(path).to_str().unwrap())),
+            None => Texture::Pure(color),
+        };
+        let (texture, surface) = if self.d < 1.0 || [4, 6, 7, 9].contains(&self.illum) {
+            let ior = Ior::Constant(if self.ni > 1.0 { self.ni } else { 1.5 });
+            let surface = if self.ns >= 1000.0 {
+                Surface::Refractive(ior)
+            } else {
+                Surface::RoughRefractive {
+                    ior,
+                    roughness,
+                    distribution: Distribution::GGX,
+                }
+            };
+            (Texture::Pure(self.tf), surface)
+        } else if self.ks.norm1() > 0.0 && self.kd.norm1() > 0.0 {
+            let principled = Principled {
+                metallic: 0.0,
+                roughness,
+                specular: 0.5,
+                transmission: 0.0,
+                clearcoat: 0.0,
+                clearcoat_roughness: 0.1,
+            };
+            (texture(self.kd), Surface::Principled(principled))
+        } else if self.ks.norm1() > 0.0 {
+            let surface = Surface::Glossy {
+                roughness,
+                distribution: Distribution::GGX,
+            };
+            (Texture::Pure(self.ks), surface)
+        } else {
+            (texture(self.kd), Surface::Diffuse)
+        };
+        let material = Material {
+            texture,
+            surface,
+            absorption: Color::empty(),
+            film: None,
+        };
+        (material, self.ke)
+    }
+}
+
+fn parse_color(args: &[&str]) -> Color {
+    let c = args
+        .iter()
+        .map(|s| s.parse::<FloatT>().expect("bad color in mtl"))
+        .collect::<Vec<_>>();
+    match c.len() {
+        1 => Color::full(c[0]),
+        3 => Color::new([c[0], c[1], c[2]]),
+        _ => panic!("bad color in mtl"),
+    }
+}
+
+/// 读取 MTL 材质库，返回材质名到材质及自发光的映射，贴图路径相对于 MTL 文件所在目录
+pub fn load_mtl(path: &str) -> HashMap<String, (Material, Color)> {
+    let data = std::fs::read_to_string(path).expect(&format!("cannot read from {}", path));
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    let mut infos: Vec<(String, MtlInfo)> = vec![];
+    for line in data.lines() {
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        if tokens.is_empty() || tokens[0].starts_with('#') {
+            continue;
+        }
+        if tokens[0] == "newmtl" {
+            infos.push((tokens[1..].join(" "), MtlInfo::default()));
+            continue;
+        }
+        let info = match infos.last_mut() {
+            Some((_, info)) => info,
+            None => continue,
+        };
+        let args = &tokens[1..];
+        let number = || args[0].parse::<FloatT>().expect("bad number in mtl");
+        match tokens[0] {
+            "Kd" => info.kd = parse_color(args),
+            "Ks" => info.ks = parse_color(args),
+            "Ke" => info.ke = parse_color(args),
+            "Tf" => info.tf = parse_color(args),
+            "Ns" => info.ns = number(),
+            "Ni" => info.ni = number(),
+            "d" => info.d = number(),
+            "Tr" => info.d = 1.0 - number(),
+            "illum" => info.illum = number() as u32,
+            // 贴图选项在文件名之前，只取最后的文件名
+            "map_Kd" => info.map_kd = args.last().map(|s| s.to_string()),
+            // 其余贴图和参数不支持，忽略
+            _ => {}
+        }
+    }
+    infos
+        .into_iter()
+        .map(|(name, info)| (name, info.into_material(dir)))
+        .collect()
+}
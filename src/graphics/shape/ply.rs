@@ -0,0 +1,341 @@
+use std::convert::TryInto;
+
+use crate::graphics::shape::MeshVertex;
+use crate::math::vector::Vector3f;
+use crate::math::FloatT;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Format {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+/// PLY 的标量类型
+#[derive(Copy, Clone)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Self {
+        use Scalar::*;
+        match name {
+            "char" | "int8" => I8,
+            "uchar" | "uint8" => U8,
+            "short" | "int16" => I16,
+            "ushort" | "uint16" => U16,
+            "int" | "int32" => I32,
+            "uint" | "uint32" => U32,
+            "float" | "float32" => F32,
+            "double" | "float64" => F64,
+            _ => panic!("unknown ply type {}", name),
+        }
+    }
+
+    fn size(self) -> usize {
+        use Scalar::*;
+        match self {
+            I8 | U8 => 1,
+            I16 | U16 => 2,
+            I32 | U32 | F32 => 4,
+            F64 => 8,
+        }
+    }
+}
+
+enum Property {
+    Scalar(String, Scalar),
+    /// 列表，先是长度再是各元素
+    List(String, Scalar, Scalar),
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// 按格式依次读出标量
+struct Reader<'a> {
+    format: Format,
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, scalar: Scalar) -> FloatT {
+        if self.format == Format::Ascii {
+            while self.pos < self.data.len() && self.data[self.pos].is_ascii_whitespace() {
+                self.pos += 1;
+            }
+            assert!(self.pos < self.data.len(), "unexpected end of PLY data");
+            let start = self.pos;
+            while self.pos < self.data.len() && !self.data[self.pos].is_ascii_whitespace() {
+                self.pos += 1;
+            }
+            return std::str::from_utf8(&self.data[start..self.pos])
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .expect("bad number in ply");
+        }
+        let size = scalar.size();
+        assert!(
+            self.pos + size <= self.data.len(),
+            "unexpected end of PLY data"
+        );
+        let mut bytes = self.data[self.pos..self.pos + size].to_vec();
+        self.pos += size;
+        if self.format == Format::BigEndian {
+            bytes.reverse();
+        }
+        use Scalar::*;
+        match scalar {
+            I8 => bytes[0] as i8 as FloatT,
+            U8 => bytes[0] as FloatT,
+            I16 => i16::from_le_bytes(bytes[..].try_into().unwrap()) as FloatT,
+            U16 => u16::from_le_bytes(bytes[..].try_into().unwrap()) as FloatT,
+            I32 => i32::from_le_bytes(bytes[..].try_into().unwrap()) as FloatT,
+            U32 => u32::from_le_bytes(bytes[..].try_into().unwrap()) as FloatT,
+            F32 => f32::from_le_bytes(bytes[..].try_into().unwrap()) as FloatT,
+            F64 => f64::from_le_bytes(bytes[..].try_into().unwrap()),
+        }
+    }
+}
+
+/// 读取 ASCII 或二进制的 PLY 文件，返回顶点及三角形的顶点下标
+/// 顶点可带法向量 nx, ny, nz 和纹理坐标 u, v（或 s, t），多边形按扇形剖分为三角形
+pub fn load_ply(path: &str) -> (Vec<MeshVertex>, Vec<[usize; 3]>) {
+    let data = std::fs::read(path).expect(&format!("cannot read from {}", path));
+    parse_ply(&data)
+}
+
+fn parse_ply(data: &[u8]) -> (Vec<MeshVertex>, Vec<[usize; 3]>) {
+    let header_end = data
+        .windows(b"end_header".len())
+        .position(|w| w == b"end_header")
+        .expect("no ply header");
+    let header = std::str::from_utf8(&data[..header_end]).expect("bad ply header");
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+    for line in header.lines() {
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        match tokens.as_slice() {
+            ["format", f, ..] => {
+                format = Some(match *f {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::LittleEndian,
+                    "binary_big_endian" => Format::BigEndian,
+                    _ => panic!("unknown ply format {}", f),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().expect("bad element count in ply"),
+                properties: vec![],
+            }),
+            ["property", "list", len, item, name] => elements
+                .last_mut()
+                .expect("property before element")
+                .properties
+                .push(Property::List(
+                    name.to_string(),
+                    Scalar::parse(len),
+                    Scalar::parse(item),
+                )),
+            ["property", ty, name] => elements
+                .last_mut()
+                .expect("property before element")
+                .properties
+                .push(Property::Scalar(name.to_string(), Scalar::parse(ty))),
+            _ => {}
+        }
+    }
+    // 数据从 end_header 所在行的下一行开始
+    let mut pos = header_end;
+    while data[pos] != b'\n' {
+        pos += 1;
+    }
+    let mut reader = Reader {
+        format: format.expect("no ply format"),
+        data,
+        pos: pos + 1,
+    };
+
+    let mut vertices = vec![];
+    let mut faces = vec![];
+    for element in &elements {
+        for _ in 0..element.count {
+            let mut scalars = vec![];
+            let mut list = vec![];
+            for property in &element.properties {
+                match property {
+                    Property::Scalar(name, scalar) => {
+                        scalars.push((name.as_str(), reader.read(*scalar)))
+                    }
+                    Property::List(name, len, item) => {
+                        let len = reader.read(*len) as usize;
+                        let items = (0..len)
+                            .map(|_| reader.read(*item) as usize)
+                            .collect::<Vec<_>>();
+                        if name == "vertex_indices" || name == "vertex_index" {
+                            list = items;
+                        }
+                    }
+                }
+            }
+            let get = |names: &[&str]| {
+                scalars
+                    .iter()
+                    .find(|(name, _)| names.contains(name))
+                    .map(|(_, value)| *value)
+            };
+            match element.name.as_str() {
+                "vertex" => {
+                    let xyz = |x, y, z| Some(Vector3f::new([get(&[x])?, get(&[y])?, get(&[z])?]));
+                    let u = get(&["u", "s", "texture_u"]);
+                    let v = get(&["v", "t", "texture_v"]);
+                    let uv = match (u, v) {
+                        (Some(u), Some(v)) => Some((u, v)),
+                        _ => None,
+                    };
+                    vertices.push(MeshVertex {
+                        pos: xyz("x", "y", "z").expect("vertex without position in ply"),
+                        normal: xyz("nx", "ny", "nz").map(|n| n.normalized()),
+                        uv,
+                    });
+                }
+                "face" => {
+                    for i in 2..list.len() {
+                        faces.push([list[0], list[i - 1], list[i]]);
+                    }
+                }
+                // 其余元素（如边）不需要
+                _ => {}
+            }
+        }
+    }
+    (vertices, faces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "element vertex 3
+property float x
+property float y
+property float z
+property double nx
+property double ny
+property double nz
+property float u
+property float v
+element face 1
+property list uchar int vertex_indices
+end_header
+";
+
+    /// 三个顶点依次为 (位置, 法向量, 纹理坐标)
+    const VERTICES: [([f32; 3], [f64; 3], [f32; 2]); 3] = [
+        ([0.0, 0.0, 0.0], [0.0, 0.0, 2.0], [0.0, 0.0]),
+        ([1.0, 2.0, 3.0], [0.0, 0.0, 2.0], [1.0, 0.0]),
+        ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [0.5, 0.25]),
+    ];
+
+    fn check((vertices, faces): (Vec<MeshVertex>, Vec<[usize; 3]>)) {
+        assert_eq!(vertices.len(), 3);
+        let pos = vertices[1].pos;
+        assert_eq!((pos.x(), pos.y(), pos.z()), (1.0, 2.0, 3.0));
+        let normal = vertices[0].normal.unwrap();
+        assert_eq!((normal.x(), normal.y(), normal.z()), (0.0, 0.0, 1.0));
+        assert_eq!(vertices[2].uv, Some((0.5, 0.25)));
+        assert_eq!(faces, vec![[0, 1, 2]]);
+    }
+
+    fn binary(format: &str, big_endian: bool) -> Vec<u8> {
+        let mut data = format!("ply\nformat {} 1.0\n{}", format, HEADER).into_bytes();
+        let mut put = |mut bytes: Vec<u8>| {
+            if big_endian {
+                bytes.reverse();
+            }
+            data.extend(bytes);
+        };
+        for (pos, normal, uv) in VERTICES.iter() {
+            pos.iter().for_each(|x| put(x.to_le_bytes().to_vec()));
+            normal.iter().for_each(|x| put(x.to_le_bytes().to_vec()));
+            uv.iter().for_each(|x| put(x.to_le_bytes().to_vec()));
+        }
+        put(vec![3]);
+        (0..3).for_each(|i: i32| put(i.to_le_bytes().to_vec()));
+        data
+    }
+
+    #[test]
+    fn ascii() {
+        let mut data = format!("ply\nformat ascii 1.0\ncomment test\n{}", HEADER);
+        for (pos, normal, uv) in VERTICES.iter() {
+            data += &format!(
+                "{} {} {} {} {} {} {} {}\n",
+                pos[0], pos[1], pos[2], normal[0], normal[1], normal[2], uv[0], uv[1]
+            );
+        }
+        data += "3 0 1 2\n";
+        check(parse_ply(data.as_bytes()));
+    }
+
+    #[test]
+    fn little_endian() {
+        check(parse_ply(&binary("binary_little_endian", false)));
+    }
+
+    #[test]
+    fn big_endian() {
+        check(parse_ply(&binary("binary_big_endian", true)));
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected end of PLY data")]
+    fn truncated_ascii() {
+        let data = format!("ply\nformat ascii 1.0\n{}0 0 0 0 0 1\n", HEADER);
+        parse_ply(data.as_bytes());
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected end of PLY data")]
+    fn truncated_binary() {
+        let mut data = binary("binary_little_endian", false);
+        data.truncate(data.len() - 2);
+        parse_ply(&data);
+    }
+
+    #[test]
+    fn polygon_fan() {
+        let data = "ply
+format ascii 1.0
+element vertex 4
+property float x
+property float y
+property float z
+element face 1
+property list uchar int vertex_index
+end_header
+0 0 0
+1 0 0
+1 1 0
+0 1 0
+4 0 1 2 3
+";
+        let (vertices, faces) = parse_ply(data.as_bytes());
+        assert_eq!(vertices.len(), 4);
+        assert!(vertices[0].normal.is_none() && vertices[0].uv.is_none());
+        assert_eq!(faces, vec![[0, 1, 2], [0, 2, 3]]);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::graphics::shape::{rand_semisphere, Plane, RandOut};
+use crate::graphics::shape::{rand_semisphere, Plane, RandOut, RandPoint};
 use crate::graphics::{HitTemp, Hittable, TextureMap};
 use crate::math::vector::Vector3f;
 use crate::math::{FloatT, Ray};
@@ -83,6 +83,19 @@ impl RandOut for Rectangle {
     }
 }
 
+impl RandPoint for Rectangle {
+    fn rand_point<R: Rng>(&self, rng: &mut R) -> (Vector3f, Vector3f, Option<(FloatT, FloatT)>) {
+        let pos = self.origin
+            + (self.x * rng.gen_range(-self.w / 2.0, self.w / 2.0)
+                + self.y * rng.gen_range(-self.h / 2.0, self.h / 2.0));
+        (pos, self.normal, None)
+    }
+
+    fn area(&self) -> FloatT {
+        self.w * self.h
+    }
+}
+
 impl TextureMap for Rectangle {
     fn texture_map(
         &self,
@@ -91,9 +104,14 @@ impl TextureMap for Rectangle {
         w: usize,
         h: usize,
     ) -> (usize, usize) {
+        let pos = pos - self.origin;
         let x = Vector3f::dot(&pos, &self.x) / self.w as FloatT + 0.5;
         let y = Vector3f::dot(&pos, &self.y) / self.h as FloatT + 0.5;
 
-        ((x * w as FloatT) as usize, (y * h as FloatT) as usize)
+        // 边界上的点可能因为舍入误差落在图像外
+        (
+            ((x * w as FloatT) as usize).min(w - 1),
+            ((y * h as FloatT) as usize).min(h - 1),
+        )
     }
 }
@@ -1,11 +1,12 @@
 use serde::Deserialize;
 
 use crate::graphics::material::{Material, Surface, Texture};
-use crate::graphics::shape::{rand_semisphere, rand_sphere, RandOut};
+use crate::graphics::shape::{rand_semisphere, rand_sphere, RandOut, RandPoint};
 use crate::graphics::{Hit, HitTemp, Hittable, Shape, TextureMap};
 use crate::math::vector::{Vector2f, Vector3f};
 use crate::math::{sqr, FloatT, Ray, PI};
 use rand::prelude::ThreadRng;
+use rand::Rng;
 
 #[derive(Deserialize, Debug)]
 pub struct Sphere {
@@ -21,6 +22,17 @@ impl RandOut for Sphere {
     }
 }
 
+impl RandPoint for Sphere {
+    fn rand_point<R: Rng>(&self, rng: &mut R) -> (Vector3f, Vector3f, Option<(FloatT, FloatT)>) {
+        let normal = rand_sphere(rng);
+        (self.center + self.radius * normal, normal, None)
+    }
+
+    fn area(&self) -> FloatT {
+        4.0 * PI * sqr(self.radius)
+    }
+}
+
 impl Hittable for Sphere {
     fn hit(&self, ray: &Ray, t_min: f64) -> Option<HitTemp> {
         let b = Vector3f::dot(&ray.direction.normalized(), &(ray.origin - self.center));
@@ -0,0 +1,98 @@
+use std::convert::TryInto;
+
+use crate::graphics::shape::MeshVertex;
+use crate::math::vector::Vector3f;
+use crate::math::FloatT;
+
+fn vertex(pos: Vector3f) -> MeshVertex {
+    MeshVertex {
+        pos,
+        normal: None,
+        uv: None,
+    }
+}
+
+/// 读取 ASCII 或二进制的 STL 文件，返回顶点及三角形的顶点下标
+/// STL 的每个三角形各自给出三个顶点，文件中的面法向量不使用，由顶点重新计算
+pub fn load_stl(path: &str) -> (Vec<MeshVertex>, Vec<[usize; 3]>) {
+    let data = std::fs::read(path).expect(&format!("cannot read from {}", path));
+    parse_stl(&data)
+}
+
+fn parse_stl(data: &[u8]) -> (Vec<MeshVertex>, Vec<[usize; 3]>) {
+    // 二进制文件的头部也可能以 solid 开头，按文件长度判断
+    let binary = data.len() >= 84 && {
+        let count = u32::from_le_bytes(data[80..84].try_into().unwrap()) as usize;
+        data.len() == 84 + 50 * count
+    };
+    let vertices = if binary {
+        data[84..]
+            .chunks(50)
+            .flat_map(|chunk| {
+                // 跳过 12 字节的面法向量，末尾 2 字节为属性
+                (0..3).map(move |i| {
+                    let f = |j: usize| {
+                        let at = 12 + 12 * i + 4 * j;
+                        f32::from_le_bytes(chunk[at..at + 4].try_into().unwrap()) as FloatT
+                    };
+                    vertex(Vector3f::new([f(0), f(1), f(2)]))
+                })
+            })
+            .collect::<Vec<_>>()
+    } else {
+        let text = std::str::from_utf8(data).expect("bad ascii stl");
+        text.lines()
+            .filter_map(|line| {
+                let tokens = line.split_whitespace().collect::<Vec<_>>();
+                match tokens.as_slice() {
+                    ["vertex", x, y, z] => {
+                        let f = |s: &str| s.parse::<FloatT>().expect("bad number in stl");
+                        Some(vertex(Vector3f::new([f(x), f(y), f(z)])))
+                    }
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+    let faces = (0..vertices.len() / 3)
+        .map(|i| [3 * i, 3 * i + 1, 3 * i + 2])
+        .collect();
+    (vertices, faces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIANGLE: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 2.0]];
+
+    fn check((vertices, faces): (Vec<MeshVertex>, Vec<[usize; 3]>)) {
+        assert_eq!(vertices.len(), 3);
+        let pos = vertices[2].pos;
+        assert_eq!((pos.x(), pos.y(), pos.z()), (0.0, 1.0, 2.0));
+        assert_eq!(faces, vec![[0, 1, 2]]);
+    }
+
+    #[test]
+    fn ascii() {
+        let mut data = "solid test\nfacet normal 0 0 1\nouter loop\n".to_string();
+        for p in TRIANGLE.iter() {
+            data += &format!("vertex {} {} {}\n", p[0], p[1], p[2]);
+        }
+        data += "endloop\nendfacet\nendsolid test\n";
+        check(parse_stl(data.as_bytes()));
+    }
+
+    #[test]
+    fn binary_with_solid_header() {
+        let mut data = b"solid but binary".to_vec();
+        data.resize(80, b' ');
+        data.extend(&1u32.to_le_bytes());
+        data.extend(&[0; 12]);
+        for x in TRIANGLE.iter().flatten() {
+            data.extend(&x.to_le_bytes());
+        }
+        data.extend(&[0; 2]);
+        check(parse_stl(&data));
+    }
+}
@@ -1,19 +1,27 @@
+use crate::graphics::shape::RandPoint;
 use crate::graphics::{Bounding, HitTemp, Hittable};
 use crate::math::matrix::Matrix3;
 use crate::math::vector::Vector3f;
-use crate::math::Ray;
+use crate::math::{FloatT, Ray};
+use rand::Rng;
 
 #[derive(Debug, Clone)]
 pub struct Triangle {
-    vertices: [Vector3f; 3],
+    pub vertices: [Vector3f; 3],
     normals: [Vector3f; 3],
+    /// 各顶点的纹理坐标
+    uvs: Option<[(FloatT, FloatT); 3]>,
     e1: Vector3f,
     e2: Vector3f,
     pub bounding: Bounding,
 }
 
 impl Triangle {
-    pub fn new(vertices: [Vector3f; 3], normals: Option<[Vector3f; 3]>) -> Self {
+    pub fn new(
+        vertices: [Vector3f; 3],
+        normals: Option<[Vector3f; 3]>,
+        uvs: Option<[(FloatT, FloatT); 3]>,
+    ) -> Self {
         let e1 = vertices[0] - vertices[1];
         let e2 = vertices[0] - vertices[2];
         let normals = normals.unwrap_or([Vector3f::cross(&e1, &e2); 3]);
@@ -21,11 +29,27 @@ impl Triangle {
         Self {
             vertices,
             normals,
+            uvs,
             e1,
             e2,
             bounding: Bounding::build(&vertices),
         }
     }
+
+    /// 按重心坐标插值纹理坐标
+    fn interpolate_uv(
+        &self,
+        alpha: FloatT,
+        beta: FloatT,
+        gamma: FloatT,
+    ) -> Option<(FloatT, FloatT)> {
+        self.uvs.map(|uvs| {
+            (
+                alpha * uvs[0].0 + beta * uvs[1].0 + gamma * uvs[2].0,
+                alpha * uvs[0].1 + beta * uvs[1].1 + gamma * uvs[2].1,
+            )
+        })
+    }
 }
 
 impl Hittable for Triangle {
@@ -43,13 +67,31 @@ impl Hittable for Triangle {
             let normal =
                 (alpha * self.normals[0] + beta * self.normals[1] + gamma * self.normals[2])
                     .normalized();
-            Some(HitTemp {
-                t,
-                normal,
-                uv: None,
-            })
+            let uv = self.interpolate_uv(alpha, beta, gamma);
+            Some(HitTemp { t, normal, uv })
         } else {
             None
         }
     }
 }
+
+impl RandPoint for Triangle {
+    fn rand_point<R: Rng>(&self, rng: &mut R) -> (Vector3f, Vector3f, Option<(FloatT, FloatT)>) {
+        let mut beta: FloatT = rng.gen_range(0.0, 1.0);
+        let mut gamma: FloatT = rng.gen_range(0.0, 1.0);
+        // 落在另一半平行四边形里时翻折回来
+        if beta + gamma > 1.0 {
+            beta = 1.0 - beta;
+            gamma = 1.0 - gamma;
+        }
+        let alpha = 1.0 - beta - gamma;
+        let pos = alpha * self.vertices[0] + beta * self.vertices[1] + gamma * self.vertices[2];
+        let normal = (alpha * self.normals[0] + beta * self.normals[1] + gamma * self.normals[2])
+            .normalized();
+        (pos, normal, self.interpolate_uv(alpha, beta, gamma))
+    }
+
+    fn area(&self) -> FloatT {
+        Vector3f::cross(&self.e1, &self.e2).length() / 2.0
+    }
+}
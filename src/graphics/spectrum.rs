@@ -0,0 +1,60 @@
+use rand::Rng;
+
+use crate::graphics::Color;
+use crate::math::matrix::Matrix3;
+use crate::math::vector::Vector3f;
+use crate::math::FloatT;
+
+/// 可见光波长范围（纳米）
+pub const WAVELENGTH_MIN: FloatT = 380.0;
+pub const WAVELENGTH_MAX: FloatT = 780.0;
+
+// 分段高斯函数，左右两侧宽度不同
+fn gaussian(x: FloatT, mu: FloatT, sigma1: FloatT, sigma2: FloatT) -> FloatT {
+    let t = (x - mu) / if x < mu { sigma1 } else { sigma2 };
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 颜色匹配函数，使用 Wyman 等人的多瓣高斯拟合
+pub fn cie_xyz(wavelength: FloatT) -> Vector3f {
+    let l = wavelength;
+    Vector3f::new([
+        1.056 * gaussian(l, 599.8, 37.9, 31.0) + 0.362 * gaussian(l, 442.0, 16.0, 26.7)
+            - 0.065 * gaussian(l, 501.1, 20.4, 26.2),
+        0.821 * gaussian(l, 568.8, 46.9, 40.5) + 0.286 * gaussian(l, 530.9, 16.3, 31.1),
+        1.217 * gaussian(l, 437.0, 11.8, 36.0) + 0.681 * gaussian(l, 459.0, 26.0, 13.8),
+    ])
+}
+
+/// XYZ 到线性 sRGB
+pub fn xyz_to_rgb(xyz: Vector3f) -> Color {
+    Matrix3([
+        [3.2406, -1.5372, -0.4986],
+        [-0.9689, 1.8758, 0.0415],
+        [0.0557, -0.2040, 1.0570],
+    ]) * xyz
+}
+
+/// 在可见光范围内均匀采样波长，并给出该波长对 RGB 各通道的权重
+/// 权重对波长的期望为 (1, 1, 1)，因此与波长无关的路径结果不变，只有色散会带来颜色
+pub struct WavelengthSampler {
+    /// 等能白光的 RGB，用于白平衡
+    white: Color,
+}
+
+impl WavelengthSampler {
+    pub fn new() -> Self {
+        let steps = 1000;
+        let step = (WAVELENGTH_MAX - WAVELENGTH_MIN) / steps as FloatT;
+        let white = (0..steps)
+            .map(|i| xyz_to_rgb(cie_xyz(WAVELENGTH_MIN + (i as FloatT + 0.5) * step)))
+            .sum::<Color>()
+            / steps as FloatT;
+        WavelengthSampler { white }
+    }
+
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> (FloatT, Color) {
+        let wavelength = rng.gen_range(WAVELENGTH_MIN, WAVELENGTH_MAX);
+        (wavelength, xyz_to_rgb(cie_xyz(wavelength)) / self.white)
+    }
+}
@@ -6,8 +6,7 @@ use serde::{Deserialize, Deserializer};
 
 use crate::math::matrix::Matrix3;
 use crate::math::vector::Vector3f;
-use crate::math::{FloatT, Ray, EPS, PI};
-use rand::prelude::ThreadRng;
+use crate::math::{sqr, FloatT, Ray, EPS, PI};
 
 pub struct Camera {
     pub center: Vector3f,
@@ -46,53 +45,111 @@ impl<'de> Deserialize<'de> for Camera {
         }
 
         let info = CameraInfo::deserialize(deserializer)?;
-        let direction = info.direction.normalized();
-        let up = info.up.normalized();
+        Ok(Camera {
+            focal: info.focal,
+            r: info.r,
+            ..Camera::new(
+                info.center,
+                info.direction,
+                info.up,
+                info.dis,
+                info.w,
+                info.h,
+                info.anti_alias,
+            )
+        })
+    }
+}
+
+impl Camera {
+    /// 小孔相机
+    pub fn new(
+        center: Vector3f,
+        direction: Vector3f,
+        up: Vector3f,
+        dis: FloatT,
+        w: usize,
+        h: usize,
+        anti_alias: usize,
+    ) -> Self {
+        let direction = direction.normalized();
+        let up = up.normalized();
         let horizontal = Vector3f::cross(&direction, &up);
         assert!(
             Vector3f::dot(&direction, &up).abs() < EPS,
             "up and direction in camera must be orthogonal"
         );
-        Ok(Camera {
-            center: info.center,
+        Camera {
+            center,
             direction,
             up,
             horizontal,
             rotate: Matrix3::from_vectors([horizontal, up, direction], true),
-            dis: info.dis,
-            cx: info.w as FloatT / 2.0,
-            cy: info.h as FloatT / 2.0,
-            w: info.w,
-            h: info.h,
-            anti_alias: info.anti_alias,
-            focal: info.focal,
-            r: info.r,
-        })
+            dis,
+            cx: w as FloatT / 2.0,
+            cy: h as FloatT / 2.0,
+            w,
+            h,
+            anti_alias,
+            focal: None,
+            r: 0.0,
+        }
     }
-}
 
-impl Camera {
     // 在同一个像素内随机产生若干条光线
-    pub fn gen(&self, x: usize, y: usize, rng: &mut ThreadRng) -> Vec<Ray> {
+    pub fn gen<R: Rng>(&self, x: usize, y: usize, rng: &mut R) -> Vec<Ray> {
         let mut rays = vec![];
         for _ in 0..self.anti_alias {
-            let x = x as FloatT + rng.gen_range(0.0, 1.0) - self.w as FloatT / 2.0;
-            let y = y as FloatT + rng.gen_range(0.0, 1.0) - self.h as FloatT / 2.0;
-            let dir = Vector3f::new([x, y, self.dis]).normalized();
-            // 如果self.focal 不是 None 则为透镜，z 轴为主光轴；否则为小孔成像
-            rays.push(if let Some(f) = self.focal {
-                let f= self.center + f / dir.z() * dir; // 手动算汇聚点
-                let r = rng.gen_range(0.0, self.r);
-                let theta = rng.gen_range(0.0, 2.0 * PI);
-                let center = self.center + r * Vector3f::new([theta.cos(), theta.sin(), 0.0]);
-                Ray::new(
-                    center,
-                    self.rotate * (f - center).normalized()
-                )
-            } else {
-                Ray::new(self.center, self.rotate * dir)
-            });
+            let x = x as FloatT + rng.gen_range(0.0, 1.0);
+            let y = y as FloatT + rng.gen_range(0.0, 1.0);
+            rays.push(self.ray(x, y, rng));
         }
         rays
     }
+
+    /// 穿过成像平面上 (x, y) 处的光线，坐标以像素为单位
+    pub fn ray<R: Rng>(&self, x: FloatT, y: FloatT, rng: &mut R) -> Ray {
+        let x = x - self.w as FloatT / 2.0;
+        let y = y - self.h as FloatT / 2.0;
+        let dir = Vector3f::new([x, y, self.dis]).normalized();
+        // 如果self.focal 不是 None 则为透镜，z 轴为主光轴；否则为小孔成像
+        if let Some(f) = self.focal {
+            let f= self.center + f / dir.z() * dir; // 手动算汇聚点
+            let r = rng.gen_range(0.0, self.r);
+            let theta = rng.gen_range(0.0, 2.0 * PI);
+            let center = self.center + r * Vector3f::new([theta.cos(), theta.sin(), 0.0]);
+            Ray::new(
+                center,
+                self.rotate * (f - center).normalized()
+            )
+        } else {
+            Ray::new(self.center, self.rotate * dir)
+        }
+    }
+
+    /// 光线方向与主光轴夹角余弦为 cos 时，针孔相机在立体角测度下生成该方向的概率密度
+    /// 每个像素在成像平面上面积为 1，整个画面共 w * h 个像素
+    pub fn pdf(&self, cos: FloatT) -> FloatT {
+        sqr(self.dis) / ((self.w * self.h) as FloatT * cos * cos * cos)
+    }
+
+    /// 针孔相机的重要性函数，满足 We * cos / pdf = 1
+    pub fn importance(&self, cos: FloatT) -> FloatT {
+        self.pdf(cos) / cos
+    }
+
+    /// 将 pos 投影到成像平面上，返回所在像素和光线方向与主光轴夹角的余弦
+    pub fn project(&self, pos: Vector3f) -> Option<((usize, usize), FloatT)> {
+        let d = self.rotate.transposed() * (pos - self.center);
+        if d.z() <= 0.0 {
+            return None;
+        }
+        let x = d.x() / d.z() * self.dis + self.cx;
+        let y = d.y() / d.z() * self.dis + self.cy;
+        if x < 0.0 || y < 0.0 || x >= self.w as FloatT || y >= self.h as FloatT {
+            None
+        } else {
+            Some(((x as usize, y as usize), d.z() / d.length()))
+        }
+    }
 }
@@ -0,0 +1,952 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::graphics::material::{Material, Principled, Surface, Texture};
+use crate::graphics::shape::{Circle, Mesh, Shape, Sphere, Triangle};
+use crate::graphics::{Bounding, Color, EmissionSide, Object};
+use crate::math::matrix::Matrix3;
+use crate::math::vector::Vector3f;
+use crate::math::{FloatT, PI};
+use crate::scene::{Camera, Scene};
+use crate::utils::Image;
+
+fn default_anti_alias() -> usize {
+    1
+}
+
+fn default_env() -> Vector3f {
+    Vector3f::empty()
+}
+
+fn default_n() -> FloatT {
+    1.0
+}
+
+/// 从 glTF 2.0 文件（.gltf 或 .glb）导入场景和相机，只支持本地文件及内嵌或相对路径的缓冲区
+#[derive(Deserialize)]
+pub struct Gltf {
+    pub path: String,
+    /// 输出图像的大小，glTF 相机只给出视场角
+    pub w: usize,
+    pub h: usize,
+    #[serde(default = "default_anti_alias")]
+    pub anti_alias: usize,
+    /// 环境光
+    #[serde(default = "default_env")]
+    pub env: Vector3f,
+    /// 环境折射率
+    #[serde(default = "default_n")]
+    pub n: FloatT,
+    /// 点光源用发光小球代替，不给出时取场景包围盒对角线长度的 1%
+    #[serde(default)]
+    pub light_radius: Option<FloatT>,
+}
+
+/// 方向光用远处的圆盘代替，圆盘半径为场景外接球半径，与场景中心的距离为其 DISTANT 倍
+const DISTANT: FloatT = 10.0;
+
+// 以下为 glTF JSON 中用到的部分
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Document {
+    #[serde(default)]
+    scene: Option<usize>,
+    #[serde(default)]
+    scenes: Vec<SceneDef>,
+    #[serde(default)]
+    nodes: Vec<Node>,
+    #[serde(default)]
+    meshes: Vec<MeshDef>,
+    #[serde(default)]
+    accessors: Vec<Accessor>,
+    #[serde(default)]
+    buffer_views: Vec<BufferView>,
+    #[serde(default)]
+    buffers: Vec<Buffer>,
+    #[serde(default)]
+    materials: Vec<MaterialDef>,
+    #[serde(default)]
+    textures: Vec<TextureDef>,
+    #[serde(default)]
+    images: Vec<ImageDef>,
+    #[serde(default)]
+    cameras: Vec<CameraDef>,
+    #[serde(default)]
+    extensions: DocumentExtensions,
+}
+
+#[derive(Deserialize)]
+struct SceneDef {
+    #[serde(default)]
+    nodes: Vec<usize>,
+}
+
+#[derive(Deserialize)]
+struct Node {
+    #[serde(default)]
+    children: Vec<usize>,
+    mesh: Option<usize>,
+    camera: Option<usize>,
+    /// 列优先的 4x4 矩阵，与 TRS 二选一
+    matrix: Option<[FloatT; 16]>,
+    translation: Option<[FloatT; 3]>,
+    /// 四元数 (x, y, z, w)
+    rotation: Option<[FloatT; 4]>,
+    scale: Option<[FloatT; 3]>,
+    #[serde(default)]
+    extensions: NodeExtensions,
+}
+
+#[derive(Deserialize)]
+struct MeshDef {
+    primitives: Vec<Primitive>,
+}
+
+#[derive(Deserialize)]
+struct Primitive {
+    attributes: HashMap<String, usize>,
+    indices: Option<usize>,
+    material: Option<usize>,
+    #[serde(default = "default_mode")]
+    mode: u32,
+}
+
+fn default_mode() -> u32 {
+    4
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Accessor {
+    buffer_view: Option<usize>,
+    #[serde(default)]
+    byte_offset: usize,
+    component_type: u32,
+    #[serde(default)]
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    ty: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BufferView {
+    buffer: usize,
+    #[serde(default)]
+    byte_offset: usize,
+    byte_length: usize,
+    byte_stride: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct Buffer {
+    uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MaterialDef {
+    #[serde(default)]
+    pbr_metallic_roughness: Pbr,
+    emissive_factor: Option<[FloatT; 3]>,
+    #[serde(default)]
+    extensions: MaterialExtensions,
+}
+
+fn one() -> FloatT {
+    1.0
+}
+
+fn white() -> [FloatT; 4] {
+    [1.0; 4]
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Pbr {
+    #[serde(default = "white")]
+    base_color_factor: [FloatT; 4],
+    base_color_texture: Option<TextureRef>,
+    #[serde(default = "one")]
+    metallic_factor: FloatT,
+    #[serde(default = "one")]
+    roughness_factor: FloatT,
+}
+
+impl Default for Pbr {
+    fn default() -> Self {
+        Pbr {
+            base_color_factor: white(),
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TextureRef {
+    index: usize,
+}
+
+#[derive(Deserialize, Default)]
+struct MaterialExtensions {
+    #[serde(rename = "KHR_materials_emissive_strength")]
+    emissive_strength: Option<EmissiveStrength>,
+    #[serde(rename = "KHR_materials_ior")]
+    ior: Option<IorExt>,
+    #[serde(rename = "KHR_materials_transmission")]
+    transmission: Option<Transmission>,
+    #[serde(rename = "KHR_materials_clearcoat")]
+    clearcoat: Option<Clearcoat>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EmissiveStrength {
+    #[serde(default = "one")]
+    emissive_strength: FloatT,
+}
+
+fn default_ior() -> FloatT {
+    1.5
+}
+
+#[derive(Deserialize)]
+struct IorExt {
+    #[serde(default = "default_ior")]
+    ior: FloatT,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Transmission {
+    #[serde(default)]
+    transmission_factor: FloatT,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Clearcoat {
+    #[serde(default)]
+    clearcoat_factor: FloatT,
+    #[serde(default)]
+    clearcoat_roughness_factor: FloatT,
+}
+
+#[derive(Deserialize)]
+struct TextureDef {
+    source: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ImageDef {
+    uri: Option<String>,
+    buffer_view: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct CameraDef {
+    perspective: Option<Perspective>,
+}
+
+#[derive(Deserialize)]
+struct Perspective {
+    yfov: FloatT,
+}
+
+#[derive(Deserialize, Default)]
+struct DocumentExtensions {
+    #[serde(rename = "KHR_lights_punctual")]
+    lights: Option<Lights>,
+}
+
+#[derive(Deserialize)]
+struct Lights {
+    lights: Vec<Light>,
+}
+
+#[derive(Deserialize)]
+struct Light {
+    #[serde(rename = "type")]
+    ty: String,
+    #[serde(default = "white3")]
+    color: [FloatT; 3],
+    #[serde(default = "one")]
+    intensity: FloatT,
+    spot: Option<Spot>,
+}
+
+fn default_outer_cone() -> FloatT {
+    PI / 4.0
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Spot {
+    #[serde(default = "default_outer_cone")]
+    outer_cone_angle: FloatT,
+}
+
+fn white3() -> [FloatT; 3] {
+    [1.0; 3]
+}
+
+#[derive(Deserialize, Default)]
+struct NodeExtensions {
+    #[serde(rename = "KHR_lights_punctual")]
+    light: Option<LightRef>,
+}
+
+#[derive(Deserialize)]
+struct LightRef {
+    light: usize,
+}
+
+/// 仿射变换 x -> linear * x + translation
+#[derive(Copy, Clone)]
+struct Transform {
+    linear: Matrix3,
+    translation: Vector3f,
+}
+
+impl Transform {
+    /// glTF 为右手系，而本项目的相机按左手系成像，将整个场景沿 z 轴镜像
+    fn mirror() -> Self {
+        Transform {
+            linear: Matrix3([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, -1.0]]),
+            translation: Vector3f::empty(),
+        }
+    }
+
+    fn of(node: &Node) -> Self {
+        if let Some(m) = node.matrix {
+            // 列优先存储，第 j 列第 i 行为 m[4 * j + i]
+            let linear = Matrix3([[m[0], m[4], m[8]], [m[1], m[5], m[9]], [m[2], m[6], m[10]]]);
+            return Transform {
+                linear,
+                translation: Vector3f::new([m[12], m[13], m[14]]),
+            };
+        }
+        let [x, y, z, w] = node.rotation.unwrap_or([0.0, 0.0, 0.0, 1.0]);
+        let rotation = Matrix3([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - z * w),
+                2.0 * (x * z + y * w),
+            ],
+            [
+                2.0 * (x * y + z * w),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - x * w),
+            ],
+            [
+                2.0 * (x * z - y * w),
+                2.0 * (y * z + x * w),
+                1.0 - 2.0 * (x * x + y * y),
+            ],
+        ]);
+        let [sx, sy, sz] = node.scale.unwrap_or([1.0; 3]);
+        let scale = Matrix3([[sx, 0.0, 0.0], [0.0, sy, 0.0], [0.0, 0.0, sz]]);
+        Transform {
+            linear: rotation * scale,
+            translation: Vector3f::new(node.translation.unwrap_or([0.0; 3])),
+        }
+    }
+
+    /// 先做 other 再做 self
+    fn then(&self, other: &Transform) -> Self {
+        Transform {
+            linear: self.linear * other.linear,
+            translation: self.linear * other.translation + self.translation,
+        }
+    }
+
+    fn point(&self, p: Vector3f) -> Vector3f {
+        self.linear * p + self.translation
+    }
+
+    fn vector(&self, v: Vector3f) -> Vector3f {
+        self.linear * v
+    }
+}
+
+/// 解码 data URI 中的 base64 数据
+fn decode_base64(s: &str) -> Vec<u8> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => c - b'A',
+        b'a'..=b'z' => c - b'a' + 26,
+        b'0'..=b'9' => c - b'0' + 52,
+        b'+' | b'-' => 62,
+        b'/' | b'_' => 63,
+        _ => panic!("bad base64 data"),
+    };
+    let digits = s
+        .bytes()
+        .filter(|c| !c.is_ascii_whitespace() && *c != b'=')
+        .map(value)
+        .collect::<Vec<_>>();
+    digits
+        .chunks(4)
+        .flat_map(|chunk| {
+            let n = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |n, (i, d)| n | (*d as u32) << (18 - 6 * i));
+            let bytes = [(n >> 16) as u8, (n >> 8) as u8, n as u8];
+            bytes[..chunk.len() - 1].to_vec()
+        })
+        .collect()
+}
+
+/// 相对路径的 uri 对应的文件路径，解码其中的 %XX 转义
+fn uri_path(dir: &Path, uri: &str) -> PathBuf {
+    let bytes = uri.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    dir.join(String::from_utf8(decoded).expect("bad uri"))
+}
+
+/// 读取 uri 指向的数据，可以是 data URI 或相对 glTF 文件的路径
+fn load_uri(dir: &Path, uri: &str) -> Vec<u8> {
+    if uri.starts_with("data:") {
+        let at = uri
+            .find(";base64,")
+            .expect("only base64 data uri is supported");
+        decode_base64(&uri[at + ";base64,".len()..])
+    } else {
+        let path = uri_path(dir, uri);
+        std::fs::read(&path).expect(&format!("cannot read from {}", path.display()))
+    }
+}
+
+struct Loader {
+    doc: Document,
+    dir: PathBuf,
+    buffers: Vec<Vec<u8>>,
+    images: HashMap<usize, Image>,
+    /// 按材质下标缓存转换结果，多个图元共用材质时不必重复处理纹理
+    materials: HashMap<Option<usize>, (Material, Color)>,
+}
+
+impl Loader {
+    fn new(path: &str) -> Self {
+        let data = std::fs::read(path).expect(&format!("cannot read from {}", path));
+        let dir = Path::new(path)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .to_path_buf();
+        let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap()) as usize;
+        // .glb 由 12 字节的文件头和若干块组成，第一块为 JSON，第二块为可选的二进制缓冲区
+        let (json, bin) = if data.starts_with(b"glTF") {
+            let mut chunks = vec![];
+            let mut at = 12;
+            while at + 8 <= data.len() {
+                let len = u32_at(at);
+                chunks.push(&data[at + 8..at + 8 + len]);
+                at += 8 + len;
+            }
+            (chunks[0], chunks.get(1).map(|c| c.to_vec()))
+        } else {
+            (&data[..], None)
+        };
+        let doc: Document = serde_json::from_slice(json).expect("cannot parse gltf");
+        let mut bin = bin;
+        let buffers = doc
+            .buffers
+            .iter()
+            .map(|buffer| match &buffer.uri {
+                Some(uri) => load_uri(&dir, uri),
+                None => bin.take().expect("missing glb binary chunk"),
+            })
+            .collect();
+        Loader {
+            doc,
+            dir,
+            buffers,
+            images: HashMap::new(),
+            materials: HashMap::new(),
+        }
+    }
+
+    fn view(&self, index: usize) -> &[u8] {
+        let view = &self.doc.buffer_views[index];
+        &self.buffers[view.buffer][view.byte_offset..view.byte_offset + view.byte_length]
+    }
+
+    /// 读出访问器的所有元素，每个元素为若干个分量
+    fn read(&self, index: usize) -> Vec<Vec<FloatT>> {
+        let accessor = &self.doc.accessors[index];
+        let comps = match accessor.ty.as_str() {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            "MAT4" => 16,
+            ty => panic!("unsupported accessor type {}", ty),
+        };
+        let view_index = match accessor.buffer_view {
+            Some(view) => view,
+            None => return vec![vec![0.0; comps]; accessor.count],
+        };
+        let size = match accessor.component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            ty => panic!("unsupported component type {}", ty),
+        };
+        let stride = self.doc.buffer_views[view_index]
+            .byte_stride
+            .unwrap_or(size * comps);
+        let data = &self.view(view_index)[accessor.byte_offset..];
+        let component = |at: usize| {
+            let bytes = &data[at..at + size];
+            let (x, max) = match accessor.component_type {
+                5120 => (bytes[0] as i8 as FloatT, 127.0),
+                5121 => (bytes[0] as FloatT, 255.0),
+                5122 => (i16::from_le_bytes([bytes[0], bytes[1]]) as FloatT, 32767.0),
+                5123 => (u16::from_le_bytes([bytes[0], bytes[1]]) as FloatT, 65535.0),
+                5125 => (u32::from_le_bytes(bytes.try_into().unwrap()) as FloatT, 1.0),
+                _ => (f32::from_le_bytes(bytes.try_into().unwrap()) as FloatT, 1.0),
+            };
+            if accessor.normalized {
+                (x / max).max(-1.0)
+            } else {
+                x
+            }
+        };
+        (0..accessor.count)
+            .map(|i| {
+                (0..comps)
+                    .map(|j| component(i * stride + j * size))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn image(&mut self, index: usize) -> Image {
+        if !self.images.contains_key(&index) {
+            let def = &self.doc.images[index];
+            let image = match (&def.uri, def.buffer_view) {
+                (Some(uri), _) if !uri.starts_with("data:") => {
+                    Image::load(uri_path(&self.dir, uri).to_str().unwrap())
+                }
+                (Some(uri), _) => Image::from_memory(&load_uri(&self.dir, uri)),
+                (None, Some(view)) => Image::from_memory(self.view(view)),
+                _ => panic!("image without data"),
+            };
+            self.images.insert(index, image);
+        }
+        self.images[&index].clone()
+    }
+
+    fn material(&mut self, index: Option<usize>) -> (Material, Color) {
+        if !self.materials.contains_key(&index) {
+            let material = self.convert_material(index);
+            self.materials.insert(index, material);
+        }
+        self.materials[&index].clone()
+    }
+
+    /// 转换为 principled 材质，基础颜色纹理乘上基础颜色系数，返回材质及自发光
+    fn convert_material(&mut self, index: Option<usize>) -> (Material, Color) {
+        let def = match index {
+            Some(index) => &self.doc.materials[index],
+            None => {
+                // glTF 规定的默认材质
+                let principled = Principled {
+                    metallic: 1.0,
+                    roughness: 1.0,
+                    specular: 0.5,
+                    transmission: 0.0,
+                    clearcoat: 0.0,
+                    clearcoat_roughness: 0.0,
+                };
+                let material = Material {
+                    texture: Texture::Pure(Color::ones()),
+                    surface: Surface::Principled(principled),
+                    absorption: Color::empty(),
+                    film: None,
+                };
+                return (material, Color::empty());
+            }
+        };
+        let pbr = &def.pbr_metallic_roughness;
+        let [r, g, b, _] = pbr.base_color_factor;
+        let factor = Color::new([r, g, b]);
+        let ext = &def.extensions;
+        let ior = ext.ior.as_ref().map_or(default_ior(), |ior| ior.ior);
+        // 法向入射时的反射率 0.08 * specular，见 `Principled::ior`
+        let f0 = ((ior - 1.0) / (ior + 1.0)).powi(2);
+        let (clearcoat, clearcoat_roughness) = ext.clearcoat.as_ref().map_or((0.0, 0.0), |c| {
+            (c.clearcoat_factor, c.clearcoat_roughness_factor)
+        });
+        let principled = Principled {
+            metallic: pbr.metallic_factor,
+            roughness: pbr.roughness_factor,
+            specular: (f0 / 0.08).min(1.0),
+            transmission: ext
+                .transmission
+                .as_ref()
+                .map_or(0.0, |t| t.transmission_factor),
+            clearcoat,
+            clearcoat_roughness,
+        };
+        let emission = Color::new(def.emissive_factor.unwrap_or([0.0; 3]))
+            * ext
+                .emissive_strength
+                .as_ref()
+                .map_or(1.0, |e| e.emissive_strength);
+        let source = pbr
+            .base_color_texture
+            .as_ref()
+            .and_then(|t| self.doc.textures[t.index].source);
+        let texture = match source {
+            Some(source) => {
+                let mut image = self.image(source);
+                for x in 0..image.w {
+                    for y in 0..image.h {
+                        image.set(x, y, image.at(x, y) * factor);
+                    }
+                }
+                Texture::Image(image)
+            }
+            None => Texture::Pure(factor),
+        };
+        let material = Material {
+            texture,
+            surface: Surface::Principled(principled),
+            absorption: Color::empty(),
+            film: None,
+        };
+        (material, emission)
+    }
+
+    /// 图元变换到世界坐标后的三角形，只支持三角形、三角形带和三角形扇
+    fn triangles(&self, primitive: &Primitive, transform: &Transform) -> Vec<Triangle> {
+        let attribute = |name: &str| primitive.attributes.get(name).map(|&i| self.read(i));
+        let points = attribute("POSITION")
+            .expect("primitive without position")
+            .iter()
+            .map(|p| transform.point(Vector3f::new([p[0], p[1], p[2]])))
+            .collect::<Vec<_>>();
+        // 法向量按逆转置矩阵变换
+        let normal_matrix = transform.linear.inv().transposed();
+        let normals = attribute("NORMAL").map(|normals| {
+            normals
+                .iter()
+                .map(|n| (normal_matrix * Vector3f::new([n[0], n[1], n[2]])).normalized())
+                .collect::<Vec<_>>()
+        });
+        // glTF 纹理坐标的原点在左上角，换成 v 轴朝上
+        let uvs = attribute("TEXCOORD_0")
+            .map(|uvs| uvs.iter().map(|t| (t[0], 1.0 - t[1])).collect::<Vec<_>>());
+        let indices = match primitive.indices {
+            Some(indices) => self
+                .read(indices)
+                .iter()
+                .map(|i| i[0] as usize)
+                .collect::<Vec<_>>(),
+            None => (0..points.len()).collect(),
+        };
+        let faces = match primitive.mode {
+            4 => indices
+                .chunks_exact(3)
+                .map(|f| [f[0], f[1], f[2]])
+                .collect::<Vec<_>>(),
+            // 三角形带中偶数个三角形的顶点顺序要翻转
+            5 => (2..indices.len())
+                .map(|i| {
+                    if i % 2 == 0 {
+                        [indices[i - 2], indices[i - 1], indices[i]]
+                    } else {
+                        [indices[i - 1], indices[i - 2], indices[i]]
+                    }
+                })
+                .collect(),
+            6 => (2..indices.len())
+                .map(|i| [indices[0], indices[i - 1], indices[i]])
+                .collect(),
+            // 点和线没有面积
+            _ => vec![],
+        };
+        // 行列式为负的变换（如 `Transform::mirror`）会把逆时针的顶点顺序变成顺时针，
+        // 交换两个顶点使没有法向量时由顶点顺序算出的法向量仍朝外
+        let flip = transform.linear.determinant() < 0.0;
+        faces
+            .iter()
+            .map(|&[a, b, c]| if flip { [a, c, b] } else { [a, b, c] })
+            .map(|[a, b, c]| {
+                Triangle::new(
+                    [points[a], points[b], points[c]],
+                    normals.as_ref().map(|n| [n[a], n[b], n[c]]),
+                    uvs.as_ref().map(|t| [t[a], t[b], t[c]]),
+                )
+            })
+            .collect()
+    }
+}
+
+impl Gltf {
+    /// 读取文件得到场景，以及场景中第一个透视相机
+    pub fn load(&self) -> (Scene, Option<Camera>) {
+        let mut loader = Loader::new(&self.path);
+        let roots = match loader.doc.scenes.get(loader.doc.scene.unwrap_or(0)) {
+            Some(scene) => scene.nodes.clone(),
+            // 没有给出场景时取所有不是其他节点子节点的节点
+            None => (0..loader.doc.nodes.len())
+                .filter(|i| !loader.doc.nodes.iter().any(|n| n.children.contains(i)))
+                .collect(),
+        };
+        // 深度优先遍历节点，得到各节点在世界坐标中的变换
+        let mut stack = roots
+            .iter()
+            .rev()
+            .map(|&i| (i, Transform::mirror()))
+            .collect::<Vec<_>>();
+        let mut placed = vec![];
+        while let Some((index, parent)) = stack.pop() {
+            let node = &loader.doc.nodes[index];
+            let transform = parent.then(&Transform::of(node));
+            placed.push((index, transform));
+            for &child in node.children.iter().rev() {
+                stack.push((child, transform));
+            }
+        }
+
+        let mut objects = vec![];
+        let mut vertices = vec![];
+        for (index, transform) in &placed {
+            let mesh = match loader.doc.nodes[*index].mesh {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            for p in 0..loader.doc.meshes[mesh].primitives.len() {
+                let primitive = &loader.doc.meshes[mesh].primitives[p];
+                let triangles = loader.triangles(primitive, transform);
+                if triangles.is_empty() {
+                    continue;
+                }
+                vertices.extend(triangles.iter().flat_map(|t| t.vertices.iter().cloned()));
+                let material = primitive.material;
+                let (material, flux) = loader.material(material);
+                let shape = Shape::Mesh(Mesh::from_triangles(triangles));
+                objects.push(Object::new(shape, material, flux));
+            }
+        }
+
+        // 场景的外接球，没有网格时取单位球
+        let (scene_center, scene_radius) = if vertices.is_empty() {
+            (Vector3f::empty(), 1.0)
+        } else {
+            let bounding = Bounding::build(&vertices);
+            (
+                (bounding.min + bounding.max) / 2.0,
+                (bounding.max - bounding.min).length() / 2.0,
+            )
+        };
+        let radius = self.light_radius.unwrap_or_else(|| {
+            if vertices.is_empty() {
+                0.01
+            } else {
+                0.02 * scene_radius
+            }
+        });
+        let black = || Material {
+            texture: Texture::Pure(Color::empty()),
+            surface: Surface::Diffuse,
+            absorption: Color::empty(),
+            film: None,
+        };
+        let lights = loader
+            .doc
+            .extensions
+            .lights
+            .as_ref()
+            .map_or(&[][..], |lights| &lights.lights[..]);
+        let mut camera = None;
+        for (index, transform) in &placed {
+            let node = &loader.doc.nodes[*index];
+            let center = transform.point(Vector3f::empty());
+            if let Some(light) = node.extensions.light.as_ref().map(|l| &lights[l.light]) {
+                match light.ty.as_str() {
+                    "point" | "spot" => {
+                        if let Some(spot) = &light.spot {
+                            println!(
+                                "spot light is approximated by a point light, \
+                                 its cone of {:.1} degrees is ignored",
+                                spot.outer_cone_angle.to_degrees()
+                            );
+                        }
+                        // 发光强度为 I 的点光源总功率为 4πI，与半径 r 的球面光源 4π^2 r^2 L 相等
+                        let flux =
+                            Color::new(light.color) * (light.intensity / (PI * radius * radius));
+                        let shape = Shape::Sphere(Sphere { center, radius });
+                        objects.push(Object::new(shape, black(), flux));
+                    }
+                    "directional" => {
+                        // 光沿局部坐标的 -z 方向照射，用场景外朝向场景单面发光的圆盘代替
+                        // 半径 a、距离 d 的圆盘在轴上的照度为 πL a^2 / (d^2 + a^2)，令其等于光照强度
+                        let dir = transform
+                            .vector(Vector3f::new([0.0, 0.0, -1.0]))
+                            .normalized();
+                        let (a, d) = (scene_radius, DISTANT * scene_radius);
+                        let flux = Color::new(light.color)
+                            * (light.intensity * (d * d + a * a) / (PI * a * a));
+                        let shape = Shape::Circle(Circle::new(scene_center - d * dir, dir, a));
+                        let mut object = Object::new(shape, black(), flux);
+                        object.emission_side = EmissionSide::Front;
+                        objects.push(object);
+                    }
+                    ty => panic!("unknown light type {}", ty),
+                }
+            }
+            let def = match node.camera.map(|c| &loader.doc.cameras[c]) {
+                Some(def) => def,
+                None => continue,
+            };
+            match (&def.perspective, &camera) {
+                (Some(perspective), None) => {
+                    // 相机看向局部坐标的 -z 方向，y 轴朝上
+                    let direction = transform
+                        .vector(Vector3f::new([0.0, 0.0, -1.0]))
+                        .normalized();
+                    let up = transform.vector(Vector3f::new([0.0, 1.0, 0.0]));
+                    // 有切变时两者不一定正交
+                    let up = up - Vector3f::dot(&up, &direction) * direction;
+                    let dis = self.h as FloatT / 2.0 / (perspective.yfov / 2.0).tan();
+                    camera = Some(Camera::new(
+                        center,
+                        direction,
+                        up,
+                        dis,
+                        self.w,
+                        self.h,
+                        self.anti_alias,
+                    ));
+                }
+                (None, _) => println!("orthographic camera is not supported"),
+                _ => {}
+            }
+        }
+        (Scene::new(objects, self.env, self.n, None), camera)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::shape::RandPoint;
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(decode_base64(""), b"");
+        assert_eq!(decode_base64("TQ=="), b"M");
+        assert_eq!(decode_base64("TWE="), b"Ma");
+        assert_eq!(decode_base64("TWFu"), b"Man");
+        assert_eq!(decode_base64("TWFuTQ"), b"ManM");
+        assert_eq!(decode_base64("TWFu\nTWE"), b"ManMa");
+        assert_eq!(decode_base64("-_8="), [0xfb, 0xff]);
+    }
+
+    #[test]
+    fn percent_encoded_uri() {
+        let dir = Path::new("scene");
+        assert_eq!(uri_path(dir, "a%20b%2Bc.png"), dir.join("a b+c.png"));
+        assert_eq!(uri_path(dir, "100%.bin"), dir.join("100%.bin"));
+    }
+
+    fn loader(json: &str, buffer: Vec<u8>) -> Loader {
+        Loader {
+            doc: serde_json::from_str(json).unwrap(),
+            dir: PathBuf::new(),
+            buffers: vec![buffer],
+            images: HashMap::new(),
+            materials: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn mirrored_winding() {
+        // xy 平面上逆时针的三角形，没有法向量，glTF 中朝 +z
+        let mut buffer = vec![];
+        for &x in &[0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            buffer.extend(&x.to_le_bytes());
+        }
+        let loader = loader(
+            r#"{
+                "bufferViews": [{"buffer": 0, "byteLength": 36}],
+                "accessors": [{
+                    "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"
+                }]
+            }"#,
+            buffer,
+        );
+        let primitive: Primitive =
+            serde_json::from_str(r#"{"attributes": {"POSITION": 0}}"#).unwrap();
+        let triangles = loader.triangles(&primitive, &Transform::mirror());
+        let (_, normal, _) = triangles[0].rand_point(&mut rand::thread_rng());
+        assert!(normal.z() < 0.0);
+    }
+
+    #[test]
+    fn strided_accessor() {
+        // 每个元素前有 4 字节的其他数据，步长为 12
+        let mut buffer = vec![];
+        for &x in &[0.0f32, 1.0, 2.0, 0.0, 3.0, 4.0] {
+            buffer.extend(&x.to_le_bytes());
+        }
+        let loader = loader(
+            r#"{
+                "bufferViews": [{"buffer": 0, "byteLength": 24, "byteStride": 12}],
+                "accessors": [{
+                    "bufferView": 0, "byteOffset": 4, "componentType": 5126,
+                    "count": 2, "type": "VEC2"
+                }]
+            }"#,
+            buffer,
+        );
+        assert_eq!(loader.read(0), vec![vec![1.0, 2.0], vec![3.0, 4.0]]);
+    }
+
+    #[test]
+    fn normalized_accessor() {
+        let mut buffer = vec![0; 2];
+        for &x in &[0u16, 65535] {
+            buffer.extend(&x.to_le_bytes());
+        }
+        let loader = loader(
+            r#"{
+                "bufferViews": [{"buffer": 0, "byteOffset": 2, "byteLength": 4}],
+                "accessors": [{
+                    "bufferView": 0, "componentType": 5123, "normalized": true,
+                    "count": 2, "type": "SCALAR"
+                }]
+            }"#,
+            buffer,
+        );
+        assert_eq!(loader.read(0), vec![vec![0.0], vec![1.0]]);
+    }
+}
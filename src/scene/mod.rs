@@ -1,31 +1,86 @@
 use std::fs;
 
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 use serde_json::Value;
 
 use crate::graphics::material::{Material, Surface};
+use crate::graphics::medium::Medium;
 use crate::graphics::{Color, HitTemp, Hittable};
 use crate::graphics::{Hit, Object};
 use crate::math::vector::Vector3f;
-use crate::math::{FloatT, Ray};
+use crate::math::{FloatT, Ray, EPS};
 use crate::utils::Image;
 
 mod camera;
+mod gltf;
 mod renderer;
 
 pub use camera::*;
+pub use gltf::*;
 pub use renderer::*;
 
-#[derive(Deserialize, Debug)]
+#[derive(Debug)]
 pub struct Scene {
     pub objects: Vec<Object>,
+    /// 可直接采样的光源在 objects 中的下标
+    pub lights: Vec<usize>,
     /// 环境光
     env: Vector3f,
     /// 环境折射率
     n: FloatT,
+    /// 环境中的介质，如雾
+    medium: Option<Medium>,
+}
+
+impl<'de> Deserialize<'de> for Scene {
+    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct SceneInfo {
+            objects: Vec<Object>,
+            env: Vector3f,
+            n: FloatT,
+            #[serde(default)]
+            medium: Option<Medium>,
+        }
+
+        let info = SceneInfo::deserialize(deserializer)?;
+        Ok(Scene::new(info.objects, info.env, info.n, info.medium))
+    }
 }
 
 impl Scene {
+    pub fn new(objects: Vec<Object>, env: Vector3f, n: FloatT, medium: Option<Medium>) -> Self {
+        let objects = objects
+            .into_iter()
+            .flat_map(Object::split)
+            .collect::<Vec<_>>();
+        let lights = objects
+            .iter()
+            .enumerate()
+            .filter(|(_, object)| object.is_light())
+            .map(|(i, _)| i)
+            .collect();
+        Scene {
+            objects,
+            lights,
+            env,
+            n,
+            medium,
+        }
+    }
+
+    /// a, b 之间是否没有遮挡
+    pub fn visible(&self, a: Vector3f, b: Vector3f) -> bool {
+        let dis = (b - a).length();
+        match self.hit(&Ray::new(a, (b - a) / dis), EPS) {
+            Some(hit) => (hit.pos - a).length() >= dis * (1.0 - 1e-6),
+            None => true,
+        }
+    }
+
     pub fn hit(&self, ray: &Ray, t_min: FloatT) -> Option<Hit> {
         if let Some((object, t, normal, uv)) = self
             .objects
@@ -0,0 +1,481 @@
+use std::sync::Mutex;
+
+use pbr::ProgressBar;
+use rand::prelude::ThreadRng;
+use rand::{thread_rng, Rng};
+use rayon::prelude::*;
+use serde::Deserialize;
+
+use crate::graphics::bsdf::{Bsdf, Interaction};
+use crate::graphics::shape::RandPoint;
+use crate::graphics::{Color, Object};
+use crate::math::vector::Vector3f;
+use crate::math::{FloatT, Ray, EPS};
+use crate::scene::renderer::{absorb, interaction};
+use crate::scene::{Camera, Render, Scene};
+use crate::utils::Image;
+
+/// 双向路径追踪
+#[derive(Deserialize)]
+pub struct BDPT {
+    /// 每条相机光线的采样数
+    pub samples: usize,
+    /// 路径的最大反射次数
+    pub max_depth: usize,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Kind {
+    Camera,
+    Light,
+    Surface,
+}
+
+#[derive(Clone)]
+struct Vertex<'a> {
+    kind: Kind,
+    pos: Vector3f,
+    /// 表面法向量，相机处为主光轴方向
+    normal: Vector3f,
+    object: Option<&'a Object>,
+    /// 表面处的散射信息
+    it: Interaction,
+    /// 此处的自发光
+    le: Color,
+    /// 从子路径起点到此处的累计权重
+    beta: Color,
+    /// 镜面反射或折射，无法与其它顶点连接
+    delta: bool,
+    /// 沿子路径方向采样到此处的概率密度（面积测度）
+    pdf_fwd: FloatT,
+    /// 沿子路径反方向采样到此处的概率密度（面积测度）
+    pdf_rev: FloatT,
+}
+
+// 概率密度为 0 的顶点是镜面顶点，在 MIS 权重中以 1 代替
+fn remap0(pdf: FloatT) -> FloatT {
+    if pdf != 0.0 {
+        pdf
+    } else {
+        1.0
+    }
+}
+
+impl<'a> Vertex<'a> {
+    /// 从 w 方向看过来的余弦，相机处为与主光轴的夹角
+    fn cos(&self, w: &Vector3f) -> FloatT {
+        Vector3f::dot(&self.normal, w).abs()
+    }
+
+    /// 将立体角测度下射向 next 的概率密度转为 next 处面积测度下的概率密度
+    fn convert(&self, pdf: FloatT, next: &Vertex) -> FloatT {
+        let d = next.pos - self.pos;
+        let dis2 = d.length2();
+        if next.kind == Kind::Camera {
+            pdf / dis2
+        } else {
+            pdf * next.cos(&(d / dis2.sqrt())) / dis2
+        }
+    }
+
+    /// 光线从 prev 来到此处后散射到 next 的 BSDF，光源处为自发光，相机处为重要性函数
+    fn f(&self, camera: &Camera, prev: Option<&Vertex>, next: &Vertex) -> Color {
+        let w = (next.pos - self.pos).normalized();
+        match self.kind {
+            Kind::Camera => match camera.project(next.pos) {
+                Some((_, cos)) => Color::full(camera.importance(cos)),
+                None => Color::empty(),
+            },
+            Kind::Light => {
+                if self.object.unwrap().emits(&self.normal, &w) {
+                    self.le
+                } else {
+                    Color::empty()
+                }
+            }
+            Kind::Surface => {
+                if self.delta {
+                    return Color::empty();
+                }
+                let wp = (prev.unwrap().pos - self.pos).normalized();
+                let surface = &self.object.unwrap().material.surface;
+                surface.eval(&self.it.toward(&wp), &wp, &w)
+            }
+        }
+    }
+
+    /// 光线从 prev 来到此处后，采样到 next 的概率密度（next 处的面积测度）
+    fn pdf(&self, camera: &Camera, prev: Option<&Vertex>, next: &Vertex) -> FloatT {
+        let w = (next.pos - self.pos).normalized();
+        let pdf = match self.kind {
+            Kind::Camera => {
+                let cos = Vector3f::dot(&self.normal, &w);
+                if cos <= 0.0 {
+                    return 0.0;
+                }
+                camera.pdf(cos)
+            }
+            Kind::Light => self.object.unwrap().emit_pdf(&self.normal, &w),
+            Kind::Surface => {
+                if self.delta {
+                    return 0.0;
+                }
+                let wp = (prev.unwrap().pos - self.pos).normalized();
+                let surface = &self.object.unwrap().material.surface;
+                surface.pdf(&self.it.toward(&wp), &wp, &w)
+            }
+        };
+        self.convert(pdf, next)
+    }
+
+    /// 此处作为光源时，对光源采样得到该点的概率密度（面积测度）
+    fn pdf_light_origin(&self, scene: &Scene) -> FloatT {
+        let object = self.object.unwrap();
+        if object.is_light() {
+            1.0 / (object.area() * scene.lights.len() as FloatT)
+        } else {
+            0.0
+        }
+    }
+
+    /// 此处作为光源时，向 next 发射的概率密度（next 处的面积测度）
+    fn pdf_light(&self, next: &Vertex) -> FloatT {
+        let w = (next.pos - self.pos).normalized();
+        self.convert(self.object.unwrap().emit_pdf(&self.normal, &w), next)
+    }
+}
+
+/// 几何项，包含可见性
+fn g(scene: &Scene, a: &Vertex, b: &Vertex) -> FloatT {
+    if !scene.visible(a.pos, b.pos) {
+        return 0.0;
+    }
+    let d = b.pos - a.pos;
+    let dis2 = d.length2();
+    let w = d / dis2.sqrt();
+    a.cos(&w) * b.cos(&w) / dis2
+}
+
+impl BDPT {
+    /// 从 ray 出发随机游走，将经过的顶点加入 path
+    /// pdf 为采样出 ray 方向的概率密度（立体角测度），返回从场景中逃逸时带回的环境光
+    fn random_walk<'a>(
+        &self,
+        scene: &'a Scene,
+        mut ray: Ray,
+        mut beta: Color,
+        mut pdf: FloatT,
+        max_len: usize,
+        path: &mut Vec<Vertex<'a>>,
+        rng: &mut ThreadRng,
+    ) -> Color {
+        let mut n_stack = vec![scene.n];
+        // 所处透射物体的吸收系数，与折射率栈同步
+        let mut absorption = vec![];
+        while path.len() < max_len {
+            let hit = match scene.hit(&ray, EPS) {
+                Some(hit) => hit,
+                None => return beta * scene.env,
+            };
+            beta *= absorb(&absorption, (hit.pos - ray.origin).length());
+            let object = hit.object;
+            let surface = &object.material.surface;
+            let it = interaction(&hit, &ray, &n_stack, None);
+            let mut vertex = Vertex {
+                kind: Kind::Surface,
+                pos: hit.pos,
+                normal: hit.normal,
+                object: Some(object),
+                it,
+                le: object.flux_toward(hit.pos, hit.uv, &hit.normal, &-ray.direction),
+                beta,
+                delta: surface.is_delta(),
+                pdf_fwd: 0.0,
+                pdf_rev: 0.0,
+            };
+            let prev = path.last_mut().unwrap();
+            vertex.pdf_fwd = prev.convert(pdf, &vertex);
+
+            let wo = -ray.direction;
+            let sample = match surface.sample(&it, &wo, rng) {
+                Some(sample) => sample,
+                None => {
+                    path.push(vertex);
+                    return Color::empty();
+                }
+            };
+            // delta 分布的概率密度记为 0
+            pdf = sample.pdf.unwrap_or(0.0);
+            let pdf_rev = if vertex.delta {
+                0.0
+            } else {
+                surface.pdf(&it.toward(&sample.dir), &sample.dir, &wo)
+            };
+            if sample.refracted {
+                if it.inside {
+                    n_stack.pop();
+                    absorption.pop();
+                } else {
+                    n_stack.push(it.ior.1);
+                    absorption.push(object.material.absorption);
+                }
+            }
+            prev.pdf_rev = vertex.convert(pdf_rev, prev);
+            path.push(vertex);
+            beta *= sample.weight;
+            ray = Ray::new(hit.pos, sample.dir);
+        }
+        Color::empty()
+    }
+
+    /// 从相机光线出发生成相机子路径，返回逃逸到环境中的光
+    fn camera_subpath<'a>(
+        &self,
+        scene: &'a Scene,
+        camera: &Camera,
+        ray: Ray,
+        path: &mut Vec<Vertex<'a>>,
+        rng: &mut ThreadRng,
+    ) -> Color {
+        path.push(Vertex {
+            kind: Kind::Camera,
+            pos: ray.origin,
+            normal: camera.direction,
+            object: None,
+            it: Interaction::new(camera.direction, &-camera.direction, Color::ones()),
+            le: Color::empty(),
+            beta: Color::ones(),
+            // 透镜相机无法与光源子路径直接连接
+            delta: camera.focal.is_some(),
+            pdf_fwd: 1.0,
+            pdf_rev: 0.0,
+        });
+        let pdf = camera.pdf(Vector3f::dot(&camera.direction, &ray.direction));
+        self.random_walk(
+            scene,
+            ray,
+            Color::ones(),
+            pdf,
+            self.max_depth + 2,
+            path,
+            rng,
+        )
+    }
+
+    /// 随机选一个光源发射光线，生成光源子路径
+    fn light_subpath<'a>(&self, scene: &'a Scene, path: &mut Vec<Vertex<'a>>, rng: &mut ThreadRng) {
+        if scene.lights.is_empty() {
+            return;
+        }
+        let light = &scene.objects[scene.lights[rng.gen_range(0, scene.lights.len())]];
+        let (pos, normal, uv) = light.rand_point(rng);
+        let pdf_pos = 1.0 / (light.area() * scene.lights.len() as FloatT);
+        path.push(Vertex {
+            kind: Kind::Light,
+            pos,
+            normal,
+            object: Some(light),
+            it: Interaction::new(normal, &-normal, Color::ones()),
+            le: light.flux_at(pos, uv),
+            beta: Color::full(1.0 / pdf_pos),
+            delta: false,
+            pdf_fwd: pdf_pos,
+            pdf_rev: 0.0,
+        });
+        let dir = light.emit_dir(&normal, rng);
+        let pdf = light.emit_pdf(&normal, &dir);
+        let beta = light.flux_at(pos, uv) * (Vector3f::dot(&normal, &dir).abs() / (pdf_pos * pdf));
+        self.random_walk(
+            scene,
+            Ray::new(pos, dir),
+            beta,
+            pdf,
+            self.max_depth + 1,
+            path,
+            rng,
+        );
+    }
+
+    /// 连接光源子路径的前 s 个顶点和相机子路径的前 t 个顶点，返回不含 MIS 权重的贡献
+    fn connect(
+        &self,
+        scene: &Scene,
+        camera: &Camera,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+        s: usize,
+        t: usize,
+    ) -> Color {
+        let pt = &camera_path[t - 1];
+        if s == 0 {
+            // 相机子路径恰好打到光源上
+            return match pt.object {
+                Some(_) if pt.kind == Kind::Surface => pt.beta * pt.le,
+                _ => Color::empty(),
+            };
+        }
+        let qs = &light_path[s - 1];
+        if qs.delta || pt.delta {
+            return Color::empty();
+        }
+        let qs_prev = if s > 1 {
+            Some(&light_path[s - 2])
+        } else {
+            None
+        };
+        let pt_prev = if t > 1 {
+            Some(&camera_path[t - 2])
+        } else {
+            None
+        };
+        let f = qs.f(camera, qs_prev, pt) * pt.f(camera, pt_prev, qs);
+        if f.norm1() == 0.0 {
+            return Color::empty();
+        }
+        qs.beta * pt.beta * f * g(scene, qs, pt)
+    }
+
+    /// 按幂启发式计算 (s, t) 策略的 MIS 权重
+    fn mis_weight(
+        &self,
+        scene: &Scene,
+        camera: &Camera,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+        s: usize,
+        t: usize,
+    ) -> FloatT {
+        if s == 0 {
+            let object = camera_path[t - 1].object.unwrap();
+            // 无法被采样的光源只能由相机子路径打到
+            if !object.is_light() {
+                return 1.0;
+            }
+        }
+        let mut light = light_path[..s].to_vec();
+        let mut cam = camera_path[..t].to_vec();
+        // 修改连接处顶点的反向概率密度
+        {
+            let pt = &cam[t - 1];
+            let pt_prev = if t > 1 { Some(&cam[t - 2]) } else { None };
+            if s > 0 {
+                let qs = &light[s - 1];
+                let qs_prev = if s > 1 { Some(&light[s - 2]) } else { None };
+                let pt_rev = qs.pdf(camera, qs_prev, pt);
+                let qs_rev = pt.pdf(camera, pt_prev, qs);
+                let qs_prev_rev = qs_prev.map(|v| qs.pdf(camera, Some(pt), v));
+                let pt_prev_rev = pt_prev.map(|v| pt.pdf(camera, Some(qs), v));
+                cam[t - 1].pdf_rev = pt_rev;
+                light[s - 1].pdf_rev = qs_rev;
+                if let Some(pdf) = qs_prev_rev {
+                    light[s - 2].pdf_rev = pdf;
+                }
+                if let Some(pdf) = pt_prev_rev {
+                    cam[t - 2].pdf_rev = pdf;
+                }
+            } else {
+                let pt_rev = pt.pdf_light_origin(scene);
+                let pt_prev_rev = pt_prev.map(|v| pt.pdf_light(v));
+                cam[t - 1].pdf_rev = pt_rev;
+                // 相机子路径的终点作为光源，不再是镜面顶点
+                cam[t - 1].delta = false;
+                if let Some(pdf) = pt_prev_rev {
+                    cam[t - 2].pdf_rev = pdf;
+                }
+            }
+        }
+
+        let sqr_ratio = |v: &Vertex| {
+            let r = remap0(v.pdf_rev) / remap0(v.pdf_fwd);
+            r * r
+        };
+        let mut sum = 0.0;
+        let mut r = 1.0;
+        for i in (1..t).rev() {
+            r *= sqr_ratio(&cam[i]);
+            if !cam[i].delta && !cam[i - 1].delta {
+                sum += r;
+            }
+        }
+        r = 1.0;
+        for i in (0..s).rev() {
+            r *= sqr_ratio(&light[i]);
+            if !light[i].delta && (i == 0 || !light[i - 1].delta) {
+                sum += r;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+}
+
+impl Render for BDPT {
+    fn render(&self, scene: &Scene, camera: &Camera, name: &str) {
+        let image = Mutex::new(Image::empty(camera.w, camera.h));
+        // 光源子路径直接连到相机上的贡献，落在任意像素
+        let splat = Mutex::new(Image::empty(camera.w, camera.h));
+        let spp = (camera.anti_alias * self.samples) as FloatT;
+        let progress_bar = Mutex::new(ProgressBar::new((camera.w * camera.h) as u64));
+        let mut pixels = vec![];
+        for i in 0..camera.w {
+            for j in 0..camera.h {
+                pixels.push((i, j));
+            }
+        }
+        pixels.into_par_iter().for_each(|(i, j)| {
+            let mut rng = thread_rng();
+            let mut color = Color::empty();
+            let mut splats = vec![];
+            let mut camera_path = vec![];
+            let mut light_path = vec![];
+            for ray in camera.gen(i, j, &mut rng) {
+                for _ in 0..self.samples {
+                    camera_path.clear();
+                    light_path.clear();
+                    color += self.camera_subpath(scene, camera, ray, &mut camera_path, &mut rng);
+                    self.light_subpath(scene, &mut light_path, &mut rng);
+                    for t in 1..=camera_path.len() {
+                        for s in 0..=light_path.len() {
+                            if s + t < 2 || s + t - 2 > self.max_depth {
+                                continue;
+                            }
+                            // 透镜相机无法直接连接
+                            if t == 1 && camera.focal.is_some() {
+                                continue;
+                            }
+                            let l = self.connect(scene, camera, &light_path, &camera_path, s, t);
+                            if l.norm1() == 0.0 {
+                                continue;
+                            }
+                            let l =
+                                l * self.mis_weight(scene, camera, &light_path, &camera_path, s, t);
+                            if t == 1 {
+                                if let Some((pixel, _)) = camera.project(light_path[s - 1].pos) {
+                                    splats.push((pixel, l));
+                                }
+                            } else {
+                                color += l;
+                            }
+                        }
+                    }
+                }
+            }
+            image.lock().unwrap().set(i, j, color / spp);
+            {
+                let mut splat = splat.lock().unwrap();
+                for ((x, y), l) in splats {
+                    splat.add(x, y, l / spp);
+                }
+            }
+            progress_bar.lock().unwrap().inc();
+        });
+        progress_bar.lock().unwrap().finish_println("done\n");
+        let mut image = image.into_inner().unwrap();
+        let splat = splat.into_inner().unwrap();
+        for i in 0..camera.w {
+            for j in 0..camera.h {
+                image.add(i, j, splat.at(i, j));
+            }
+        }
+        image.dump(name, true);
+    }
+}
@@ -0,0 +1,277 @@
+use std::sync::Mutex;
+
+use pbr::ProgressBar;
+use rand::rngs::StdRng;
+use rand::{Error, Rng, RngCore, SeedableRng};
+use rayon::prelude::*;
+use serde::Deserialize;
+
+use crate::graphics::Color;
+use crate::math::{FloatT, PI};
+use crate::scene::{Camera, Render, Scene, PT};
+use crate::utils::Image;
+
+/// 与链的编号异或得到接受随机数的种子，与所有采样器的种子都不同
+const ACCEPT_STREAM: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// 主样本空间 Metropolis 光线传输（Kelemen），在 PT 的路径采样之上变异随机数向量
+#[derive(Deserialize)]
+pub struct MLT {
+    /// 每个像素平均的变异次数
+    pub mutations: usize,
+    /// 用于估计归一化常数和选取初始状态的独立样本数
+    pub bootstrap: usize,
+    /// 马尔可夫链数目
+    pub chains: usize,
+    /// 大步变异（重新生成所有随机数）的概率
+    pub large_step: FloatT,
+    /// 小步变异的标准差
+    pub sigma: FloatT,
+    /// 同 PT
+    pub max_depth: usize,
+}
+
+/// 主样本空间中的一维
+#[derive(Default, Clone)]
+struct PrimarySample {
+    value: FloatT,
+    /// 最近一次修改的迭代轮次
+    modified: usize,
+    value_backup: FloatT,
+    modified_backup: usize,
+}
+
+/// 可重放的采样器：同一个随机数向量总能得到同一条路径
+/// 各维在被用到时才按迭代轮次补齐变异，因此路径长度可以不同
+pub struct MLTSampler {
+    rng: StdRng,
+    sigma: FloatT,
+    large_step_prob: FloatT,
+    x: Vec<PrimarySample>,
+    iteration: usize,
+    large_step: bool,
+    last_large_step: usize,
+    index: usize,
+}
+
+impl MLTSampler {
+    /// 由 seed 确定的采样器，第一条路径的随机数全部重新生成
+    pub fn new(seed: u64, sigma: FloatT, large_step_prob: FloatT) -> Self {
+        MLTSampler {
+            rng: StdRng::seed_from_u64(seed),
+            sigma,
+            large_step_prob,
+            x: vec![],
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            index: 0,
+        }
+    }
+
+    /// 之后的变异改用 seed 对应的随机数
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// 开始一次变异
+    pub fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen_range(0.0, 1.0) < self.large_step_prob;
+        self.index = 0;
+    }
+
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    /// 撤销本次变异
+    pub fn reject(&mut self) {
+        for x in &mut self.x {
+            if x.modified == self.iteration {
+                x.value = x.value_backup;
+                x.modified = x.modified_backup;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    /// 取出下一维的值，在 [0, 1) 中
+    pub fn next(&mut self) -> FloatT {
+        if self.index >= self.x.len() {
+            self.x.push(PrimarySample::default());
+        }
+        let x = &mut self.x[self.index];
+        self.index += 1;
+        // 上次大步变异之后没用到的维需要先重新生成
+        if x.modified < self.last_large_step {
+            x.value = self.rng.gen_range(0.0, 1.0);
+            x.modified = self.last_large_step;
+        }
+        x.value_backup = x.value;
+        x.modified_backup = x.modified;
+        if self.large_step {
+            x.value = self.rng.gen_range(0.0, 1.0);
+        } else {
+            // 错过的若干次小步变异合成一次，正态分布的方差相加
+            let sigma = self.sigma * ((self.iteration - x.modified) as FloatT).sqrt();
+            let r: FloatT = self.rng.gen_range(FloatT::EPSILON, 1.0);
+            let theta = self.rng.gen_range(0.0, 2.0 * PI);
+            x.value += sigma * (-2.0 * r.ln()).sqrt() * theta.cos();
+            x.value -= x.value.floor();
+        }
+        x.modified = self.iteration;
+        x.value
+    }
+}
+
+/// 使 PT 等使用 Rng 的代码可以直接从采样器中取随机数
+impl RngCore for MLTSampler {
+    fn next_u32(&mut self) -> u32 {
+        (self.next() * 4294967296.0) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (self.next() * 18446744073709551616.0) as u64
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        Ok(self.fill_bytes(dest))
+    }
+}
+
+impl MLT {
+    /// 用采样器中的随机数生成一条完整路径，返回所在像素和辐射亮度
+    fn sample(
+        &self,
+        pt: &PT,
+        scene: &Scene,
+        camera: &Camera,
+        sampler: &mut MLTSampler,
+    ) -> ((usize, usize), Color) {
+        let x = sampler.gen_range(0.0, camera.w as FloatT);
+        let y = sampler.gen_range(0.0, camera.h as FloatT);
+        let ray = camera.ray(x, y, sampler);
+        let color = pt.path_tracing(scene, ray, None, sampler);
+        (
+            (
+                (x as usize).min(camera.w - 1),
+                (y as usize).min(camera.h - 1),
+            ),
+            color,
+        )
+    }
+}
+
+impl Render for MLT {
+    fn render(&self, scene: &Scene, camera: &Camera, name: &str) {
+        let pt = PT {
+            samples: 1,
+            max_depth: self.max_depth,
+            spectral: false,
+        };
+
+        println!("bootstrap");
+        // 标量贡献取各通道之和
+        let weights = (0..self.bootstrap)
+            .into_par_iter()
+            .map(|i| {
+                let mut sampler = MLTSampler::new(i as u64, self.sigma, self.large_step);
+                self.sample(&pt, scene, camera, &mut sampler).1.norm1()
+            })
+            .collect::<Vec<_>>();
+        let mut cdf = vec![0.0];
+        for w in &weights {
+            cdf.push(cdf.last().unwrap() + w);
+        }
+        let total = *cdf.last().unwrap();
+        // 归一化常数，即图像平面上的平均标量贡献
+        let b = total / self.bootstrap as FloatT;
+        let image = Mutex::new(Image::empty(camera.w, camera.h));
+        if b <= 0.0 {
+            image.lock().unwrap().dump(name, true);
+            return;
+        }
+
+        println!("mutate");
+        let total_mutations = self.mutations * camera.w * camera.h;
+        let progress_bar = Mutex::new(ProgressBar::new(self.chains as u64));
+        (0..self.chains).into_par_iter().for_each(|chain| {
+            // 各条链的随机数都由链的编号确定，结果可以复现
+            let seed = (self.bootstrap + chain) as u64;
+            // 选取初始状态和接受与否用的随机数与采样器的变异相互独立，否则两者相关使链有偏
+            let mut rng = StdRng::seed_from_u64(seed ^ ACCEPT_STREAM);
+            // 按 bootstrap 样本的贡献选取初始状态
+            let u = rng.gen_range(0.0, total);
+            let index = cdf[1..]
+                .iter()
+                .position(|&x| x > u)
+                .unwrap_or(self.bootstrap - 1);
+            let mut sampler = MLTSampler::new(index as u64, self.sigma, self.large_step);
+            let (mut pixel, mut color) = self.sample(&pt, scene, camera, &mut sampler);
+            sampler.reseed(seed);
+
+            let mut local = Image::empty(camera.w, camera.h);
+            let begin = total_mutations * chain / self.chains;
+            let end = total_mutations * (chain + 1) / self.chains;
+            for _ in begin..end {
+                sampler.start_iteration();
+                let (new_pixel, new_color) = self.sample(&pt, scene, camera, &mut sampler);
+                let accept = if color.norm1() > 0.0 {
+                    (new_color.norm1() / color.norm1()).min(1.0)
+                } else {
+                    1.0
+                };
+                // 期望值技巧：当前状态和提议状态都按接受概率计入
+                if accept > 0.0 {
+                    local.add(
+                        new_pixel.0,
+                        new_pixel.1,
+                        new_color * (accept / new_color.norm1()),
+                    );
+                }
+                if accept < 1.0 {
+                    local.add(pixel.0, pixel.1, color * ((1.0 - accept) / color.norm1()));
+                }
+                if rng.gen_range(0.0, 1.0) < accept {
+                    pixel = new_pixel;
+                    color = new_color;
+                    sampler.accept();
+                } else {
+                    sampler.reject();
+                }
+            }
+
+            {
+                let mut image = image.lock().unwrap();
+                for i in 0..camera.w {
+                    for j in 0..camera.h {
+                        image.add(i, j, local.at(i, j));
+                    }
+                }
+            }
+            progress_bar.lock().unwrap().inc();
+        });
+        progress_bar.lock().unwrap().finish_println("done\n");
+
+        // 每次变异代表图像平面上 b / 总变异数 的贡献，换成像素均值需乘像素数
+        let image = image.into_inner().unwrap();
+        let scale = b * (camera.w * camera.h) as FloatT / total_mutations as FloatT;
+        let mut result = Image::empty(camera.w, camera.h);
+        for i in 0..camera.w {
+            for j in 0..camera.h {
+                result.set(i, j, image.at(i, j) * scale);
+            }
+        }
+        result.dump(name, true);
+    }
+}
@@ -3,54 +3,32 @@ use serde::Deserialize;
 use crate::scene::{Camera, Scene};
 use crate::utils::Image;
 
+mod bdpt;
+mod mlt;
 mod ppm;
 mod pt;
+mod vcm;
 
-use crate::math::vector::Vector3f;
-use crate::math::{sqr, FloatT, Ray, EPS};
+use crate::graphics::bsdf::Interaction;
+use crate::graphics::{Color, Hit};
+use crate::math::{FloatT, Ray};
+pub use bdpt::*;
+pub use mlt::*;
 pub use ppm::*;
 pub use pt::*;
+pub use vcm::*;
 
 pub trait Render {
     fn render(&self, scene: &Scene, camera: &Camera, name: &str);
-    fn refractive(
-        &self,
-        i: &Vector3f,
-        normal: &Vector3f,
-        n: FloatT,
-        nt: FloatT,
-    ) -> (FloatT, Option<(FloatT, Vector3f)>) {
-        let nt2 = nt * nt;
-        let dn = Vector3f::dot(i, normal);
-        let delta = { nt2 - n * n * (1.0 - dn * dn) };
-
-        // 全反射
-        if delta <= 0.0 {
-            (1.0, None)
-        } else {
-            // 折射光方向
-            let t = (n * (*i - *normal * dn) / nt - *normal * (delta / nt2).sqrt()).normalized();
-            assert!(Vector3f::dot(&t, normal) <= 0.0);
-
-            // 计算反射光强占比
-            let r = {
-                let r0 = sqr((nt - n) / (nt + n));
-                let c = if n <= nt {
-                    Vector3f::dot(i, normal).abs()
-                } else {
-                    Vector3f::dot(&t, &normal).abs()
-                };
-                r0 + (1.0 - r0) * (1.0 - c).powi(5)
-            };
-            (r, Some((1.0 - r, t)))
-        }
-    }
 }
 
 #[derive(Deserialize)]
 pub enum Renderer {
     PT(PT),
     PPM(PPM),
+    BDPT(BDPT),
+    VCM(VCM),
+    MLT(MLT),
 }
 
 impl Renderer {
@@ -59,6 +37,9 @@ impl Renderer {
         match self {
             PT(pt) => pt.render(scene, camera, name),
             PPM(ppm) => ppm.render(scene, camera, name),
+            BDPT(bdpt) => bdpt.render(scene, camera, name),
+            VCM(vcm) => vcm.render(scene, camera, name),
+            MLT(mlt) => mlt.render(scene, camera, name),
         }
     }
 }
@@ -81,3 +62,45 @@ fn get_n(inside: bool, n_stack: &Vec<FloatT>, nt: &FloatT) -> (FloatT, FloatT) {
         }
     }
 }
+
+// 光线 ray 打到 hit 处时的散射信息，会折射的表面由折射率栈得到两侧的折射率
+fn interaction(
+    hit: &Hit,
+    ray: &Ray,
+    n_stack: &Vec<FloatT>,
+    wavelength: Option<FloatT>,
+) -> Interaction {
+    let mut it = Interaction::new(
+        hit.normal,
+        &ray.direction,
+        hit.object.color_at(hit.pos, hit.uv),
+    );
+    if let Some(nt) = hit.object.material.surface.ior(wavelength) {
+        it.ior = get_n(it.inside, n_stack, &nt);
+    }
+    it.film = hit.object.material.film;
+    it.wavelength = wavelength;
+    it
+}
+
+// 光线在吸收栈顶的物体内部走过 dis 距离后的透射比（Beer–Lambert 定律），栈为空时处在环境中
+fn absorb(absorption: &[Color], dis: FloatT) -> Color {
+    match absorption.last() {
+        Some(a) => Color::new([
+            (-a[0] * dis).exp(),
+            (-a[1] * dis).exp(),
+            (-a[2] * dis).exp(),
+        ]),
+        None => Color::ones(),
+    }
+}
+
+// 多重重要性采样的幂启发式（beta = 2），pdf 为当前策略的概率密度，other 为另一策略的
+fn power_heuristic(pdf: FloatT, other: FloatT) -> FloatT {
+    let (a, b) = (pdf * pdf, other * other);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
@@ -5,17 +5,17 @@ use rand::prelude::*;
 use rayon::prelude::*;
 use serde::Deserialize;
 
-use crate::graphics::material::Surface;
-use crate::graphics::shape::{rand_semisphere, RandOut};
-use crate::graphics::{Color, Hit};
-use crate::math::matrix::Matrix3;
+use crate::graphics::bsdf::Bsdf;
+use crate::graphics::medium::Medium;
+use crate::graphics::Color;
 use crate::math::vector::Vector3f;
-use crate::math::{sqr, FloatT, Ray, EPS, PI, ZERO};
-use crate::scene::{renderer::get_n, Camera, Render, Scene};
+use crate::math::{sqr, FloatT, Ray, EPS, PI};
+use crate::scene::renderer::{absorb, interaction};
+use crate::scene::{Camera, Render, Scene};
 use crate::utils::{kdtree, Image, Positionable};
 use std::cmp::min;
 use std::ptr::drop_in_place;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 #[derive(Deserialize)]
 pub struct PPM {
@@ -24,6 +24,19 @@ pub struct PPM {
     init_radius: FloatT,
     alpha: FloatT,
     photon_num: usize,
+    /// 随机渐进式光子映射（SPPM）：每轮重新追踪相机光线生成视点，使抗锯齿、景深能够收敛
+    #[serde(default)]
+    stochastic: bool,
+    // 停止条件，满足任一条即停止，都未给出时一直迭代
+    /// 最大迭代轮数
+    max_iter: Option<usize>,
+    /// 发射的光子总数上限
+    max_photons: Option<usize>,
+    /// 运行时间上限（秒）
+    time_limit: Option<FloatT>,
+    /// 相邻两轮累计图像之间的均方误差（见 `Image::mse`）低于该值时停止
+    /// 衡量的是图像的变化量而不是像素估计值的方差，估计收敛时随迭代轮数下降
+    target_change: Option<FloatT>,
 }
 
 #[derive(Clone)]
@@ -43,7 +56,8 @@ struct ViewPoint {
 impl ViewPoint {
     fn update(&mut self, photons: Vec<Photon>, alpha: FloatT) {
         let m = photons.len();
-        if m == 0 {
+        // 权重为零说明本轮没有有效的视点（SPPM 中光线可能未打到漫反射面）
+        if m == 0 || self.weight.norm1() == 0.0 {
             return;
         }
         let gain = alpha * m as FloatT;
@@ -76,17 +90,73 @@ impl Positionable for Photon {
     }
 }
 
+/// 介质中沿视线步进得到的体积视点，与表面视点一样逐轮缩小收集半径
+struct VolumePoint<'a> {
+    pos: Vector3f,
+    /// 指向观察者的方向
+    dir: Vector3f,
+    pixel: (usize, usize),
+    /// 眼睛到此处的衰减系数，已乘上透射率和步长
+    weight: Color,
+    medium: &'a Medium,
+    /// 累计光子数
+    n: usize,
+    radius: FloatT,
+    flux: Color,
+}
+
+impl VolumePoint<'_> {
+    /// 与 `ViewPoint::update` 相同，但收集到的光子数正比于 r^3，半径按立方根缩小
+    fn update(&mut self, photons: Vec<VolumePhoton>, alpha: FloatT) {
+        let m = photons.len();
+        if m == 0 {
+            return;
+        }
+        let gain = alpha * m as FloatT;
+        let ratio = (self.n as FloatT + gain) / (self.n + m) as FloatT;
+        self.n += gain as usize;
+        self.radius *= ratio.cbrt();
+        for photon in photons {
+            self.flux += self.weight * photon.flux * self.medium.phase(&photon.dir, &self.dir);
+        }
+        self.flux *= ratio;
+    }
+
+    /// 累计 iter 轮后的散射光估计，与表面的估计保持相同的比例：表面为 flux / r^2，省略了 PI^2
+    fn estimate(&self, iter: usize) -> Color {
+        self.flux / (iter as FloatT * 4.0 / 3.0 * self.radius.powi(3) / PI)
+    }
+}
+
+/// 在介质中散射的光子
+#[derive(Clone)]
+struct VolumePhoton {
+    pos: Vector3f,
+    flux: Color,
+    /// 散射前的传播方向
+    dir: Vector3f,
+}
+
+impl Positionable for VolumePhoton {
+    fn pos(&self) -> Vector3f {
+        self.pos
+    }
+}
+
 impl PPM {
     // 返回值为所有直接光照
-    fn ray_tracing(
+    fn ray_tracing<'a>(
         &self,
-        scene: &Scene,
+        scene: &'a Scene,
         ray: Ray,
         n_stack: Vec<FloatT>,
+        media: Vec<Option<&'a Medium>>,
+        absorption: Vec<Color>,
         pixel: (usize, usize),
         depth: usize,
         mut weight: Color,
         view_points: &mut Vec<ViewPoint>,
+        volume_points: &mut Vec<VolumePoint<'a>>,
         rng: &mut ThreadRng,
     ) -> Color {
         // 俄罗斯赌轮，防止无限递归
@@ -95,114 +165,100 @@ impl PPM {
                 return scene.env;
             }
         }
-        if let Some(Hit {
-            pos,
-            mut normal,
-            uv,
-            object,
-        }) = scene.hit(&ray, EPS)
-        {
-            let color = object.color_at(pos, uv);
-            weight *= color;
-            object.flux
-                + color
-                    * match &object.material.surface {
-                        Surface::Diffuse => {
-                            if Vector3f::dot(&normal, &ray.direction) > 0.0 {
-                                // 调整为反射平面的法向量
-                                normal = -normal;
+        let hit = scene.hit(&ray, EPS);
+        let t_max = hit
+            .as_ref()
+            .map_or(FloatT::INFINITY, |hit| (hit.pos - ray.origin).length());
+        // 在介质中沿光线步进生成体积视点，之后的贡献按透射率衰减
+        let tr = match media.last().cloned().flatten() {
+            Some(medium) => {
+                let step = self.init_radius;
+                let mut t = rng.gen_range(0.0, step);
+                while t < t_max.min(medium.cutoff(&ray)) {
+                    volume_points.push(VolumePoint {
+                        pos: ray.at(t),
+                        dir: -ray.direction,
+                        pixel,
+                        weight: weight
+                            * medium.transmittance(&ray, t, rng)
+                            * absorb(&absorption, t)
+                            * step,
+                        medium,
+                        n: 0,
+                        radius: self.init_radius,
+                        flux: Color::empty(),
+                    });
+                    t += step;
+                }
+                medium.transmittance(&ray, t_max, rng)
+            }
+            None => Color::ones(),
+        };
+        // 打到表面时再乘上透射物体内部的吸收
+        let tr = tr
+            * hit
+                .as_ref()
+                .map_or(Color::ones(), |_| absorb(&absorption, t_max));
+        weight *= tr;
+        tr * if let Some(hit) = hit {
+            let object = hit.object;
+            let surface = &object.material.surface;
+            let mut it = interaction(&hit, &ray, &n_stack, None);
+            let wo = -ray.direction;
+            // 漫反射部分存为视点，由光子图估计
+            let diffuse = surface.diffuse(&it);
+            if diffuse.norm1() > 0.0 {
+                view_points.push(ViewPoint {
+                    pos: hit.pos,
+                    dir: wo,
+                    pixel,
+                    weight: weight * diffuse,
+                    n: 0,
+                    radius: self.init_radius,
+                    flux: Color::empty(),
+                });
+            }
+            // 其余部分继续追踪，非随机模式下 delta 分布的各个分支都要追踪
+            it.diffuse = false;
+            let samples = if surface.is_delta() && !self.stochastic {
+                surface.branches(&it, &wo)
+            } else {
+                surface.sample(&it, &wo, rng).into_iter().collect()
+            };
+            object.flux_toward(hit.pos, hit.uv, &hit.normal, &wo)
+                + samples
+                    .into_iter()
+                    .map(|sample| {
+                        let mut n_stack = n_stack.clone();
+                        let mut media = media.clone();
+                        let mut absorption = absorption.clone();
+                        if sample.refracted {
+                            if it.inside {
+                                n_stack.pop();
+                                media.pop();
+                                absorption.pop();
+                            } else {
+                                n_stack.push(it.ior.1);
+                                media.push(object.medium.as_ref());
+                                absorption.push(object.material.absorption);
                             }
-                            view_points.push(ViewPoint {
-                                pos,
-                                dir: -ray.direction,
-                                pixel,
-                                weight,
-                                n: 0,
-                                radius: self.init_radius,
-                                flux: Color::empty(),
-                            });
-                            Color::empty()
                         }
-                        Surface::Specular => {
-                            // 此时一定在物体外侧，因为不可能进入反射的材质
-                            self.ray_tracing(
+                        sample.weight
+                            * self.ray_tracing(
                                 scene,
-                                Ray::new(
-                                    pos,
-                                    ray.direction
-                                        - normal * 2.0 * Vector3f::dot(&normal, &ray.direction),
-                                ),
-                                n_stack.clone(),
+                                Ray::new(hit.pos, sample.dir),
+                                n_stack,
+                                media,
+                                absorption,
                                 pixel,
                                 depth + 1,
-                                weight,
+                                weight * sample.weight,
                                 view_points,
+                                volume_points,
                                 rng,
                             )
-                        }
-                        Surface::Refractive(nt) => {
-                            let inside = if Vector3f::dot(&normal, &ray.direction) > 0.0 {
-                                // (-normal, true)
-                                normal = -normal;
-                                true
-                            } else {
-                                false
-                            };
-
-                            // 当前折射率和即将进入的介质的折射率
-                            let (n, nt) = get_n(inside, &n_stack, nt);
-
-                            let (re, tr) = self.refractive(&ray.direction, &normal, n, nt);
-                            if let Some((tr, t)) = tr {
-                                assert!(Vector3f::dot(&t, &normal) <= 0.0);
-                                let re_t = ray.direction
-                                    - normal * 2.0 * Vector3f::dot(&normal, &ray.direction);
-                                assert!(Vector3f::dot(&normal, &re_t) >= 0.0);
-                                re * self.ray_tracing(
-                                    scene,
-                                    Ray::new(pos, re_t),
-                                    n_stack.clone(),
-                                    pixel,
-                                    depth + 1,
-                                    weight * re,
-                                    view_points,
-                                    rng,
-                                ) + tr * {
-                                    let mut new_stack = n_stack.clone();
-                                    if inside {
-                                        new_stack.pop();
-                                    } else {
-                                        new_stack.push(nt);
-                                    }
-                                    self.ray_tracing(
-                                        scene,
-                                        Ray::new(pos, t),
-                                        new_stack,
-                                        pixel,
-                                        depth + 1,
-                                        weight * tr,
-                                        view_points,
-                                        rng,
-                                    )
-                                }
-                            } else {
-                                re * self.ray_tracing(
-                                    scene,
-                                    Ray::new(
-                                        pos,
-                                        ray.direction
-                                            - normal * 2.0 * Vector3f::dot(&normal, &ray.direction),
-                                    ),
-                                    n_stack.clone(),
-                                    pixel,
-                                    depth + 1,
-                                    weight * re,
-                                    view_points,
-                                    rng,
-                                )
-                            }
-                        }
-                    }
+                    })
+                    .sum::<Color>()
         } else {
             scene.env
         }
@@ -213,9 +269,12 @@ impl PPM {
         scene: &Scene,
         ray: Ray,
         n_stack: Vec<FloatT>,
+        media: Vec<Option<&Medium>>,
+        absorption: Vec<Color>,
         mut flux: Color,
         depth: usize,
         photons: &mut Vec<Photon>,
+        volume_photons: &mut Vec<VolumePhoton>,
         rng: &mut ThreadRng,
     ) {
         if depth > 2 {
@@ -223,113 +282,303 @@ impl PPM {
                 return;
             }
         }
-        if let Some(Hit {
-            pos,
-            mut normal,
-            uv,
-            object,
-        }) = scene.hit(&ray, EPS)
-        {
-            match &object.material.surface {
-                Surface::Diffuse => {
-                    if Vector3f::dot(&normal, &ray.direction) > 0.0 {
-                        // 调整为反射平面的法向量
-                        normal = -normal;
+        let hit = scene.hit(&ray, EPS);
+        let t_max = hit
+            .as_ref()
+            .map_or(FloatT::INFINITY, |hit| (hit.pos - ray.origin).length());
+        // 在介质中采样自由程，在打到表面之前散射时存下体积光子
+        if let Some(medium) = media.last().cloned().flatten() {
+            let (t, weight) = medium.sample(&ray, t_max, rng);
+            flux *= weight;
+            if let Some(t) = t {
+                flux *= absorb(&absorption, t);
+                let pos = ray.at(t);
+                volume_photons.push(VolumePhoton {
+                    pos,
+                    flux,
+                    dir: ray.direction,
+                });
+                return self.photon_tracing(
+                    scene,
+                    Ray::new(pos, medium.sample_phase(&ray.direction, rng)),
+                    n_stack,
+                    media,
+                    absorption,
+                    flux,
+                    depth + 1,
+                    photons,
+                    volume_photons,
+                    rng,
+                );
+            }
+        }
+        if let Some(hit) = hit {
+            flux *= absorb(&absorption, t_max);
+            let object = hit.object;
+            let surface = &object.material.surface;
+            let it = interaction(&hit, &ray, &n_stack, None);
+            let wo = -ray.direction;
+            // 光子只存在有漫反射成分的表面上
+            if surface.diffuse(&it).norm1() > 0.0 {
+                photons.push(Photon {
+                    pos: hit.pos,
+                    flux,
+                    // dir: ray.direction,
+                });
+            }
+            let samples = if surface.is_delta() && !self.stochastic {
+                surface.branches(&it, &wo)
+            } else {
+                surface.sample(&it, &wo, rng).into_iter().collect()
+            };
+            for sample in samples {
+                let mut n_stack = n_stack.clone();
+                let mut media = media.clone();
+                let mut absorption = absorption.clone();
+                if sample.refracted {
+                    if it.inside {
+                        n_stack.pop();
+                        media.pop();
+                        absorption.pop();
+                    } else {
+                        n_stack.push(it.ior.1);
+                        media.push(object.medium.as_ref());
+                        absorption.push(object.material.absorption);
                     }
-                    photons.push(Photon {
-                        pos,
-                        flux,
-                        // dir: ray.direction,
-                    });
-                    self.photon_tracing(
-                        scene,
-                        Ray::new(pos, rand_semisphere(&normal, rng)),
-                        n_stack.clone(),
-                        flux * object.color_at(pos, uv),
-                        depth + 1,
-                        photons,
-                        rng,
-                    );
-                }
-                Surface::Specular => {
-                    self.photon_tracing(
-                        scene,
-                        Ray::new(
-                            pos,
-                            ray.direction - normal * 2.0 * Vector3f::dot(&normal, &ray.direction),
-                        ),
-                        n_stack.clone(),
-                        flux * object.color_at(pos, uv),
-                        depth + 1,
-                        photons,
-                        rng,
-                    );
                 }
-                Surface::Refractive(nt) => {
-                    flux *= object.color_at(pos, uv);
-                    let inside = if Vector3f::dot(&normal, &ray.direction) > 0.0 {
-                        // (-normal, true)
-                        normal = -normal;
-                        true
-                    } else {
-                        false
-                    };
+                self.photon_tracing(
+                    scene,
+                    Ray::new(hit.pos, sample.dir),
+                    n_stack,
+                    media,
+                    absorption,
+                    flux * sample.weight,
+                    depth + 1,
+                    photons,
+                    volume_photons,
+                    rng,
+                );
+            }
+        }
+    }
 
-                    // 当前折射率和即将进入的介质的折射率
-                    let (n, nt) = get_n(inside, &n_stack, nt);
-                    let (re, tr) = self.refractive(&ray.direction, &normal, n, nt);
-                    if let Some((tr, t)) = tr {
-                        self.photon_tracing(
-                            scene,
-                            Ray::new(
-                                pos,
-                                ray.direction
-                                    - normal * 2.0 * Vector3f::dot(&normal, &ray.direction),
-                            ),
-                            n_stack.clone(),
-                            flux * re,
-                            depth + 1,
-                            photons,
-                            rng,
-                        );
-                        let mut new_stack = n_stack.clone();
-                        if inside {
-                            new_stack.pop();
-                        } else {
-                            new_stack.push(nt);
-                        }
+    /// 是否满足停止条件，change 为本轮与上一轮图像的均方误差
+    fn finished(
+        &self,
+        iter: usize,
+        photons: usize,
+        elapsed: Duration,
+        change: Option<FloatT>,
+    ) -> bool {
+        self.max_iter.map_or(false, |max| iter >= max)
+            || self.max_photons.map_or(false, |max| photons >= max)
+            || self
+                .time_limit
+                .map_or(false, |limit| elapsed.as_secs_f64() >= limit)
+            || match (self.target_change, change) {
+                (Some(target), Some(change)) => change <= target,
+                _ => false,
+            }
+    }
+
+    /// 从所有光源按能量比例发射光子，建立光子图和体积光子图，同时返回发射的光子数
+    fn photon_pass(
+        &self,
+        scene: &Scene,
+    ) -> (
+        Box<kdtree::Node<Photon>>,
+        Option<Box<kdtree::Node<VolumePhoton>>>,
+        usize,
+    ) {
+        let energy = scene
+            .objects
+            .iter()
+            .map(|object| object.flux.norm1())
+            .collect::<Vec<_>>();
+        let tot_energy = energy.iter().sum::<FloatT>();
+        println!("energy: {:?}", energy);
+        let photons = Mutex::new(Vec::<Photon>::new());
+        let volume_photons = Mutex::new(Vec::<VolumePhoton>::new());
+        let mut emitted = 0;
+        println!("building photon map...");
+        for (object, energy) in scene.objects.iter().zip(energy) {
+            let photon_num = (self.photon_num as FloatT * energy / tot_energy + 0.5) as usize;
+            emitted += photon_num;
+            (0..photon_num)
+                .into_par_iter()
+                .chunks(100)
+                .for_each(|chunk| {
+                    let chunk: Vec<usize> = chunk;
+                    let mut rng = thread_rng();
+                    let mut cur = Vec::new();
+                    let mut cur_volume = Vec::new();
+                    for _ in chunk.iter() {
+                        let (ray, flux) = object.emit(&mut rng);
+                        // println!("ray: {:?}", ray);
                         self.photon_tracing(
                             scene,
-                            Ray::new(pos, t),
-                            new_stack,
-                            flux * tr,
-                            depth + 1,
-                            photons,
-                            rng,
+                            ray,
+                            vec![scene.n],
+                            vec![scene.medium.as_ref()],
+                            vec![],
+                            flux / photon_num as FloatT,
+                            0,
+                            &mut cur,
+                            &mut cur_volume,
+                            &mut rng,
                         );
-                    } else {
-                        self.photon_tracing(
+                    }
+                    photons.lock().unwrap().extend(cur);
+                    volume_photons.lock().unwrap().extend(cur_volume);
+                });
+        }
+        let photons = photons.into_inner().unwrap();
+        let len = photons.len();
+        let ret = kdtree::new(photons);
+        println!("photon map size: {}", len);
+        let volume_photons = volume_photons.into_inner().unwrap();
+        let volume_map = if volume_photons.is_empty() {
+            None
+        } else {
+            println!("volume photon map size: {}", volume_photons.len());
+            Some(kdtree::new(volume_photons))
+        };
+        (ret, volume_map, emitted)
+    }
+
+    /// 用本轮的体积光子图更新各体积视点，alpha 为 1 时半径不变，只累计通量
+    fn volume_pass(
+        &self,
+        points: &mut [VolumePoint],
+        volume_map: &Option<Box<kdtree::Node<VolumePhoton>>>,
+        alpha: FloatT,
+    ) {
+        if let Some(volume_map) = volume_map {
+            points.par_iter_mut().for_each(|point| {
+                point.update(volume_map.within(&point.pos, point.radius), alpha);
+            });
+        }
+    }
+
+    // 每个像素的每条光线对应一组视点，每轮重新追踪光线更新其位置和权重，半径、光子数和通量在各轮间保留
+    fn render_stochastic(&self, scene: &Scene, camera: &Camera, name: &str) {
+        let spp = camera.anti_alias;
+        let mut view_points = vec![Vec::<ViewPoint>::new(); camera.w * camera.h * spp];
+        // 各轮直接光照之和
+        let mut direct = Image::empty(camera.w, camera.h);
+        // 各轮介质散射光之和
+        let mut volume = Image::empty(camera.w, camera.h);
+        // 体积视点每轮重新生成，无法逐点缩小半径，改为所有视点共用按轮数缩小的半径
+        // 即 r_{i+1}^3 = r_i^3 * (i + alpha) / (i + 1)
+        let mut volume_radius = self.init_radius;
+        let start = Instant::now();
+        let mut emitted = 0;
+        let mut last: Option<Image> = None;
+        for iter in 1.. {
+            let now = Instant::now();
+            println!("iteration {}", iter);
+            println!("eye pass");
+            let colors = view_points
+                .par_chunks_mut(spp)
+                .enumerate()
+                .map(|(k, chunk)| {
+                    let (i, j) = (k / camera.h, k % camera.h);
+                    let mut rng = thread_rng();
+                    let mut color = Color::empty();
+                    let mut volume_points = Vec::new();
+                    for (slots, ray) in chunk.iter_mut().zip(camera.gen(i, j, &mut rng)) {
+                        let mut cur = Vec::new();
+                        color += self.ray_tracing(
                             scene,
-                            Ray::new(
-                                pos,
-                                ray.direction
-                                    - normal * 2.0 * Vector3f::dot(&normal, &ray.direction),
-                            ),
-                            n_stack.clone(),
-                            flux * re,
-                            depth + 1,
-                            photons,
-                            rng,
+                            ray,
+                            vec![scene.n],
+                            vec![scene.medium.as_ref()],
+                            vec![],
+                            (i, j),
+                            0,
+                            Color::ones(),
+                            &mut cur,
+                            &mut volume_points,
+                            &mut rng,
                         );
+                        // 一条光线可能产生多个视点（如 principled 材质存下漫反射部分后继续追踪），
+                        // 路径上的第 k 个视点更新第 k 个槽位，本轮没有用到的槽位权重为零
+                        for (k, slot) in slots.iter_mut().enumerate() {
+                            match cur.get(k) {
+                                Some(new) => {
+                                    slot.pos = new.pos;
+                                    slot.dir = new.dir;
+                                    slot.weight = new.weight;
+                                }
+                                None => slot.weight = Color::empty(),
+                            }
+                        }
+                        let used = slots.len();
+                        slots.extend(cur.into_iter().skip(used));
                     }
+                    ((i, j), color / spp as FloatT, volume_points)
+                })
+                .collect::<Vec<_>>();
+            let mut volume_points = Vec::new();
+            for ((i, j), color, points) in colors {
+                direct.add(i, j, color);
+                volume_points.extend(points);
+            }
+
+            let (photon_map, volume_map, num) = self.photon_pass(scene);
+            emitted += num;
+
+            println!("updating...");
+            for point in &mut volume_points {
+                point.radius = volume_radius;
+            }
+            self.volume_pass(&mut volume_points, &volume_map, 1.0);
+            for point in &volume_points {
+                volume.add(
+                    point.pixel.0,
+                    point.pixel.1,
+                    point.estimate(1) / spp as FloatT,
+                );
+            }
+            volume_radius *= ((iter as FloatT + self.alpha) / (iter + 1) as FloatT).cbrt();
+            let mut image = Image::empty(camera.w, camera.h);
+            for i in 0..camera.w {
+                for j in 0..camera.h {
+                    image.set(i, j, (direct.at(i, j) + volume.at(i, j)) / iter as FloatT);
                 }
             }
+            let image = Mutex::new(image);
+            view_points.par_iter_mut().flatten().for_each(|view_point| {
+                view_point.update(
+                    photon_map.within(&view_point.pos, view_point.radius),
+                    self.alpha,
+                );
+                image.lock().unwrap().add(
+                    view_point.pixel.0,
+                    view_point.pixel.1,
+                    view_point.flux / ((iter * spp) as FloatT * sqr(view_point.radius)),
+                );
+            });
+
+            let image = image.into_inner().unwrap();
+            image.dump(name, true);
+            println!("image updated");
+            println!("iteration elapsed: {}ms", now.elapsed().as_millis());
+            let change = last.map(|last| image.mse(&last));
+            if self.finished(iter, emitted, start.elapsed(), change) {
+                println!("finished after {} iterations", iter);
+                break;
+            }
+            last = Some(image);
         }
     }
 }
 
 impl Render for PPM {
     fn render(&self, scene: &Scene, camera: &Camera, name: &str) {
+        if self.stochastic {
+            return self.render_stochastic(scene, camera, name);
+        }
         let mut pixels = vec![];
         for i in 0..camera.w {
             for j in 0..camera.h {
@@ -337,11 +586,13 @@ impl Render for PPM {
             }
         }
         let view_points = Mutex::new(Vec::<ViewPoint>::new());
+        let volume_points = Mutex::new(Vec::<VolumePoint>::new());
         println!("eye pass");
         // 处理光源的颜色
         let direct = Mutex::new(Image::empty(camera.w, camera.h));
         pixels.into_par_iter().for_each(|(i, j)| {
             let mut cur = Vec::new();
+            let mut cur_volume = Vec::new();
             let mut rng = thread_rng();
             let color = camera
                 .gen(i, j, &mut rng)
@@ -351,10 +602,13 @@ impl Render for PPM {
                         scene,
                         *ray,
                         vec![scene.n],
+                        vec![scene.medium.as_ref()],
+                        vec![],
                         (i, j),
                         0,
                         Color::new([1.0, 1.0, 1.0]),
                         &mut cur,
+                        &mut cur_volume,
                         &mut rng,
                     )
                 })
@@ -362,57 +616,31 @@ impl Render for PPM {
                 / camera.anti_alias as FloatT;
             direct.lock().unwrap().set(i, j, color);
             view_points.lock().unwrap().extend(cur);
+            volume_points.lock().unwrap().extend(cur_volume);
         });
         println!("view points num: {}", view_points.lock().unwrap().len());
         let direct = direct.into_inner().unwrap();
+        let mut volume_points = volume_points.into_inner().unwrap();
+        let start = Instant::now();
+        let mut emitted = 0;
+        let mut last: Option<Image> = None;
         for iter in 1.. {
             let now = Instant::now();
             println!("iteration {}", iter);
-            let energy = scene
-                .objects
-                .iter()
-                .map(|object| object.flux.norm1())
-                .collect::<Vec<_>>();
-            let tot_energy = energy.iter().sum::<FloatT>();
-            println!("energy: {:?}", energy);
-            let photons = Mutex::new(Vec::<Photon>::new());
-            println!("building photon map...");
-            for (object, energy) in scene.objects.iter().zip(energy) {
-                let photon_num = (self.photon_num as FloatT * energy / tot_energy + 0.5) as usize;
-                let flux = object.flux / photon_num as FloatT;
-                (0..photon_num)
-                    .into_par_iter()
-                    .chunks(100)
-                    .for_each(|chunk| {
-                        let chunk: Vec<usize> = chunk;
-                        let mut rng = thread_rng();
-                        let mut cur = Vec::new();
-                        for _ in chunk.iter() {
-                            let ray = object.rand_out(&mut rng);
-                            // println!("ray: {:?}", ray);
-                            self.photon_tracing(
-                                scene,
-                                ray,
-                                vec![scene.n],
-                                flux,
-                                0,
-                                &mut cur,
-                                &mut rng,
-                            );
-                        }
-                        photons.lock().unwrap().extend(cur);
-                    });
-            }
-            let photons = photons.into_inner().unwrap();
-            let photon_map = {
-                let len = photons.len();
-                let ret = kdtree::new(photons);
-                println!("photon map size: {}", len);
-                ret
-            };
+            let (photon_map, volume_map, num) = self.photon_pass(scene);
+            emitted += num;
 
             println!("updating...");
-            let image = Mutex::new(direct.clone());
+            self.volume_pass(&mut volume_points, &volume_map, self.alpha);
+            let mut image = direct.clone();
+            for point in &volume_points {
+                image.add(
+                    point.pixel.0,
+                    point.pixel.1,
+                    point.estimate(iter) / camera.anti_alias as FloatT,
+                );
+            }
+            let image = Mutex::new(image);
             view_points
                 .lock()
                 .unwrap()
@@ -433,9 +661,16 @@ impl Render for PPM {
                     );
                 });
 
-            image.lock().unwrap().dump(name, true);
+            let image = image.into_inner().unwrap();
+            image.dump(name, true);
             println!("image updated");
             println!("iteration elapsed: {}ms", now.elapsed().as_millis());
+            let change = last.map(|last| image.mse(&last));
+            if self.finished(iter, emitted, start.elapsed(), change) {
+                println!("finished after {} iterations", iter);
+                break;
+            }
+            last = Some(image);
         }
     }
 }
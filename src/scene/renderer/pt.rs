@@ -4,131 +4,207 @@ use rand::{thread_rng, Rng};
 use rayon::prelude::*;
 use serde::Deserialize;
 
-use crate::graphics::material::Surface;
-use crate::graphics::shape::rand_semisphere;
-use crate::graphics::{Color, Hit};
-use crate::math::matrix::Matrix3;
+use crate::graphics::bsdf::Bsdf;
+use crate::graphics::medium::Medium;
+use crate::graphics::shape::RandPoint;
+use crate::graphics::spectrum::WavelengthSampler;
+use crate::graphics::Color;
 use crate::math::vector::Vector3f;
-use crate::math::{sqr, FloatT, Ray, EPS, PI, ZERO};
-use crate::scene::{renderer::get_n, Camera, Render, Scene};
+use crate::math::{FloatT, Ray, EPS};
+use crate::scene::renderer::{absorb, interaction, power_heuristic};
+use crate::scene::{Camera, Render, Scene};
 use crate::utils::Image;
 use image::math::utils::clamp;
 use pbr::ProgressBar;
-use rand::prelude::ThreadRng;
 
 #[derive(Deserialize)]
 pub struct PT {
     /// 采样数
     pub samples: usize,
+    /// 超过该深度后按吞吐量进行俄罗斯轮盘赌
     pub max_depth: usize,
+    /// 光谱模式：每条路径随机选取一个波长，色散材质按该波长折射
+    #[serde(default)]
+    pub spectral: bool,
 }
 
 impl PT {
-    // n_stack: 折射率栈
-    pub fn path_tracing(
+    /// 在 pos 处随机选一个光源并在其表面采样一点，估计直接光照（已乘上 MIS 权重）
+    /// scatter 给出朝某方向散射的值（表面为 BSDF 乘 cos，介质中为相函数）及采样到该方向的概率密度
+    /// medium 为 pos 所在的介质，absorption 为 pos 所在物体的吸收栈，阴影光线穿过它们时会衰减
+    fn direct_light<R: Rng>(
         &self,
         scene: &Scene,
-        ray: Ray,
-        n_stack: Vec<FloatT>,
-        depth: usize,
-        rng: &mut ThreadRng,
+        pos: Vector3f,
+        medium: Option<&Medium>,
+        absorption: &[Color],
+        scatter: impl Fn(&Vector3f) -> Option<(Color, FloatT)>,
+        rng: &mut R,
     ) -> Color {
-        if let Some(Hit {
-            pos,
-            mut normal,
-            uv,
-            object,
-        }) = scene.hit(&ray, EPS)
-        {
-            let illumination = || {
-                if depth == self.max_depth {
-                    return scene.env;
-                }
-                match &object.material.surface {
-                    Surface::Diffuse => {
-                        if Vector3f::dot(&normal, &ray.direction) > 0.0 {
-                            // 调整为反射平面的法向量
-                            normal = -normal;
-                        }
-                        let dir = rand_semisphere(&normal, rng);
-                        assert!(Vector3f::dot(&normal, &dir) >= 0.0);
-                        self.path_tracing(scene, Ray::new(pos, dir), n_stack, depth + 1, rng)
+        if scene.lights.is_empty() {
+            return Color::empty();
+        }
+        let light = &scene.objects[scene.lights[rng.gen_range(0, scene.lights.len())]];
+        let (p, n, uv) = light.rand_point(rng);
+        let dis2 = (p - pos).length2();
+        let dir = (p - pos) / dis2.sqrt();
+        let cos_light = Vector3f::dot(&n, &dir).abs();
+        let (f, scatter_pdf) = match scatter(&dir) {
+            Some(ret) => ret,
+            None => return Color::empty(),
+        };
+        if cos_light <= 0.0 || !scene.visible(pos, p) {
+            return Color::empty();
+        }
+        // 面积测度下的概率密度为 1 / (area * |lights|)，换到立体角测度需乘 dis2 / cos_light
+        let pdf = dis2 / (cos_light * light.area() * scene.lights.len() as FloatT);
+        // 介质只在物体表面处改变，而 visible 要求两点之间没有任何表面，
+        // 所以阴影光线全程都在 pos 所在的介质和吸收物体中，不会穿过其他物体的介质
+        let tr = medium.map_or(Color::ones(), |medium| {
+            medium.transmittance(&Ray::new(pos, dir), dis2.sqrt(), rng)
+        }) * absorb(absorption, dis2.sqrt());
+        light.flux_toward(p, uv, &n, &-dir) * tr * f * (power_heuristic(pdf, scatter_pdf) / pdf)
+    }
+
+    /// 俄罗斯轮盘赌，存活概率取决于吞吐量，存活后补偿权重保证无偏；返回 false 表示路径终止
+    fn roulette<R: Rng>(&self, depth: usize, throughput: &mut Color, rng: &mut R) -> bool {
+        if depth >= self.max_depth {
+            let p = throughput[0]
+                .max(throughput[1])
+                .max(throughput[2])
+                .min(0.95);
+            if rng.gen_range(0.0, 1.0) >= p {
+                return false;
+            }
+            *throughput /= p;
+        }
+        true
+    }
+
+    /// 沿 ray 追踪一条完整路径，返回沿 ray 反方向到达的辐射亮度
+    /// 所有随机数都取自 rng，传入可重放的采样器即可复现同一条路径
+    /// wavelength 为路径的波长（纳米），None 时折射率取标称值
+    pub fn path_tracing<R: Rng>(
+        &self,
+        scene: &Scene,
+        mut ray: Ray,
+        wavelength: Option<FloatT>,
+        rng: &mut R,
+    ) -> Color {
+        let mut color = Color::empty();
+        // 路径到当前顶点为止的累计衰减
+        let mut throughput = Color::ones();
+        // 折射率栈，光源和相机只能处在环境中 T_T
+        let mut n_stack = vec![scene.n];
+        // 与折射率栈同步，记录当前所处的介质
+        let mut media = vec![scene.medium.as_ref()];
+        // 所处透射物体的吸收系数，进出物体时与折射率栈同步
+        let mut absorption: Vec<Color> = vec![];
+        // 采样出 ray 方向的概率密度，用于与光源采样做 MIS；
+        // None 表示镜面反射、折射或相机光线，光源采样无法得到这条路径，自发光全部计入
+        let mut pdf: Option<FloatT> = None;
+        for depth in 0.. {
+            let hit = scene.hit(&ray, EPS);
+            let t_max = hit
+                .as_ref()
+                .map_or(FloatT::INFINITY, |hit| (hit.pos - ray.origin).length());
+            // 在介质中先采样自由程，在打到表面之前散射时路径在介质中继续
+            if let Some(medium) = media.last().cloned().flatten() {
+                let (t, weight) = medium.sample(&ray, t_max, rng);
+                throughput *= weight;
+                if let Some(t) = t {
+                    throughput *= absorb(&absorption, t);
+                    if !self.roulette(depth, &mut throughput, rng) {
+                        break;
                     }
-                    Surface::Specular => {
-                        // 此时一定在物体外侧，因为不可能进入反射的材质
-                        self.path_tracing(
+                    let pos = ray.at(t);
+                    let wi = ray.direction;
+                    color += throughput
+                        * self.direct_light(
                             scene,
-                            Ray::new(
-                                pos,
-                                ray.direction
-                                    - normal * 2.0 * Vector3f::dot(&normal, &ray.direction),
-                            ),
-                            n_stack.clone(),
-                            depth + 1,
+                            pos,
+                            Some(medium),
+                            &absorption,
+                            |dir| {
+                                let phase = medium.phase(&wi, dir);
+                                Some((Color::full(phase), phase))
+                            },
                             rng,
-                        )
+                        );
+                    // 按相函数采样，相函数与概率密度相消
+                    let dir = medium.sample_phase(&wi, rng);
+                    pdf = Some(medium.phase(&wi, &dir));
+                    ray = Ray::new(pos, dir);
+                    continue;
+                }
+            }
+            let hit = match hit {
+                Some(hit) => hit,
+                None => {
+                    color += throughput * scene.env;
+                    break;
+                }
+            };
+            throughput *= absorb(&absorption, t_max);
+            let object = hit.object;
+            let flux = object.flux_toward(hit.pos, hit.uv, &hit.normal, &-ray.direction);
+            color += throughput
+                * match pdf {
+                    Some(pdf) if object.is_light() => {
+                        let light_pdf =
+                            object.pdf(&ray.origin, &ray.direction) / scene.lights.len() as FloatT;
+                        flux * power_heuristic(pdf, light_pdf)
                     }
-                    Surface::Refractive(nt) => {
-                        let inside = if Vector3f::dot(&normal, &ray.direction) > 0.0 {
-                            // (-normal, true)
-                            normal = -normal;
-                            true
-                        } else {
-                            false
-                        };
+                    _ => flux,
+                };
 
-                        // 当前折射率和即将进入的介质的折射率
-                        let (n, nt) = get_n(inside, &n_stack, nt);
+            if !self.roulette(depth, &mut throughput, rng) {
+                break;
+            }
 
-                        let (re, tr) = self.refractive(&ray.direction, &normal, n, nt);
-                        if let Some((tr, t)) = tr {
-                            assert!(Vector3f::dot(&t, &normal) <= 0.0);
-                            re * self.path_tracing(
-                                scene,
-                                Ray::new(
-                                    pos,
-                                    ray.direction
-                                        - normal * 2.0 * Vector3f::dot(&normal, &ray.direction),
-                                ),
-                                n_stack.clone(),
-                                depth + 1,
-                                rng,
-                            ) + tr * {
-                                let mut new_stack = n_stack.clone();
-                                if inside {
-                                    new_stack.pop();
-                                } else {
-                                    new_stack.push(nt);
-                                }
-                                self.path_tracing(
-                                    scene,
-                                    Ray::new(pos, t),
-                                    new_stack,
-                                    depth + 1,
-                                    rng,
-                                )
+            let surface = &object.material.surface;
+            let it = interaction(&hit, &ray, &n_stack, wavelength);
+            let wo = -ray.direction;
+            if !surface.is_delta() {
+                color += throughput
+                    * self.direct_light(
+                        scene,
+                        hit.pos,
+                        media.last().cloned().flatten(),
+                        &absorption,
+                        |dir| {
+                            let pdf = surface.pdf(&it, &wo, dir);
+                            if pdf > 0.0 {
+                                let cos = Vector3f::dot(&it.normal, dir).abs();
+                                Some((surface.eval(&it, &wo, dir) * cos, pdf))
+                            } else {
+                                None
                             }
-                        } else {
-                            // 全反射
-                            re * self.path_tracing(
-                                scene,
-                                Ray::new(
-                                    pos,
-                                    ray.direction
-                                        - normal * 2.0 * Vector3f::dot(&normal, &ray.direction),
-                                ),
-                                n_stack.clone(),
-                                depth + 1,
-                                rng,
-                            )
-                        }
-                    }
-                }
+                        },
+                        rng,
+                    );
+            }
+            // 按 BSDF 采样，delta 分布的方向无法由光源采样得到，自发光全部计入
+            let sample = match surface.sample(&it, &wo, rng) {
+                Some(sample) => sample,
+                None => break,
             };
-            object.flux + object.color_at(pos, uv) * illumination()
-        } else {
-            scene.env
+            throughput *= sample.weight;
+            pdf = sample.pdf;
+            if sample.refracted {
+                if it.inside {
+                    n_stack.pop();
+                    media.pop();
+                    absorption.pop();
+                } else {
+                    n_stack.push(it.ior.1);
+                    media.push(object.medium.as_ref());
+                    absorption.push(object.material.absorption);
+                }
+            }
+            ray = Ray::new(hit.pos, sample.dir);
         }
+        color
     }
 }
 
@@ -142,6 +218,7 @@ impl Render for PT {
                 pixels.push((i, j));
             }
         }
+        let sampler = WavelengthSampler::new();
         pixels.into_par_iter().for_each(|(i, j)| {
             let mut rng = thread_rng();
             let rays = camera.gen(i, j, &mut rng);
@@ -150,8 +227,12 @@ impl Render for PT {
                 .map(|ray| {
                     let mut color = Color::empty();
                     for _ in 0..self.samples {
-                        // 光源和相机只能处在环境中 T_T
-                        color += self.path_tracing(scene, ray.clone(), vec![scene.n], 0, &mut rng);
+                        color += if self.spectral {
+                            let (wavelength, weight) = sampler.sample(&mut rng);
+                            weight * self.path_tracing(scene, *ray, Some(wavelength), &mut rng)
+                        } else {
+                            self.path_tracing(scene, *ray, None, &mut rng)
+                        };
                     }
                     color /= self.samples as FloatT;
                     Vector3f::new([
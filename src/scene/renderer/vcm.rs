@@ -0,0 +1,541 @@
+use std::sync::Mutex;
+use std::time::Instant;
+
+use rand::prelude::ThreadRng;
+use rand::{thread_rng, Rng};
+use rayon::prelude::*;
+use serde::Deserialize;
+
+use crate::graphics::bsdf::{Bsdf, Interaction};
+use crate::graphics::material::{Material, Surface};
+use crate::graphics::shape::RandPoint;
+use crate::graphics::Color;
+use crate::math::vector::Vector3f;
+use crate::math::{FloatT, Ray, EPS, PI};
+use crate::scene::renderer::{absorb, interaction};
+use crate::scene::{Camera, Render, Scene};
+use crate::utils::{kdtree, Image, Positionable};
+
+/// 顶点连接与合并：统一双向路径追踪的连接和光子映射的合并
+#[derive(Deserialize)]
+pub struct VCM {
+    /// 迭代轮数，每轮每个像素追踪 anti_alias 条相机子路径和一条光源子路径
+    pub iterations: usize,
+    /// 路径的最大反射次数
+    pub max_depth: usize,
+    /// 初始合并半径
+    pub init_radius: FloatT,
+    /// 半径缩减系数，第 i 轮半径的平方为初始的 i^(alpha - 1) 倍
+    pub alpha: FloatT,
+}
+
+/// 子路径的状态，dvcm, dvc, dvm 为递推计算 MIS 权重所需的量
+struct SubPath {
+    ray: Ray,
+    throughput: Color,
+    /// 已经过的边数
+    length: usize,
+    dvcm: FloatT,
+    dvc: FloatT,
+    dvm: FloatT,
+    n_stack: Vec<FloatT>,
+    /// 所处透射物体的吸收系数，与折射率栈同步
+    absorption: Vec<Color>,
+}
+
+/// 光源子路径上的非镜面顶点，用于连接与合并
+#[derive(Clone)]
+struct LightVertex {
+    pos: Vector3f,
+    surface: Surface,
+    it: Interaction,
+    /// 指向子路径上一个顶点的方向
+    wi: Vector3f,
+    throughput: Color,
+    length: usize,
+    dvcm: FloatT,
+    dvc: FloatT,
+    dvm: FloatT,
+}
+
+impl Positionable for LightVertex {
+    fn pos(&self) -> Vector3f {
+        self.pos
+    }
+}
+
+/// 每轮的合并半径及由此得到的权重
+struct Radius {
+    /// 合并相对连接的 MIS 权重因子
+    vm_weight: FloatT,
+    /// 连接相对合并的 MIS 权重因子
+    vc_weight: FloatT,
+    /// 合并的核函数归一化系数
+    vm_normalization: FloatT,
+    radius: FloatT,
+}
+
+// 幂启发式
+fn mis(x: FloatT) -> FloatT {
+    x * x
+}
+
+/// 表面的 BSDF 求值，fixed 为已知一侧的方向，gen 为另一侧的方向
+/// 返回 BSDF 值、gen 的余弦、由 fixed 采样到 gen 及反向采样的概率密度（立体角测度）
+fn eval(
+    surface: &Surface,
+    it: &Interaction,
+    fixed: &Vector3f,
+    gen: &Vector3f,
+) -> Option<(Color, FloatT, FloatT, FloatT)> {
+    let it_fixed = it.toward(fixed);
+    let f = surface.eval(&it_fixed, fixed, gen);
+    let pdf = surface.pdf(&it_fixed, fixed, gen);
+    if f.norm1() <= 0.0 || pdf <= 0.0 {
+        None
+    } else {
+        Some((
+            f,
+            Vector3f::dot(&it.normal, gen).abs(),
+            pdf,
+            surface.pdf(&it.toward(gen), gen, fixed),
+        ))
+    }
+}
+
+impl VCM {
+    /// 打到表面后更新吞吐量和 MIS 量，返回入射方向与法向量夹角的余弦
+    fn arrive(&self, state: &mut SubPath, pos: Vector3f, normal: &Vector3f) -> FloatT {
+        let cos = Vector3f::dot(normal, &state.ray.direction).abs();
+        let dis2 = (pos - state.ray.origin).length2();
+        state.throughput *= absorb(&state.absorption, dis2.sqrt());
+        state.dvcm *= mis(dis2);
+        state.dvcm /= mis(cos);
+        state.dvc /= mis(cos);
+        state.dvm /= mis(cos);
+        cos
+    }
+
+    /// 采样散射方向并更新子路径状态，返回 false 表示路径终止
+    fn scatter(
+        &self,
+        state: &mut SubPath,
+        pos: Vector3f,
+        material: &Material,
+        it: &Interaction,
+        radius: &Radius,
+        rng: &mut ThreadRng,
+    ) -> bool {
+        let surface = &material.surface;
+        let wo = -state.ray.direction;
+        let sample = match surface.sample(it, &wo, rng) {
+            Some(sample) => sample,
+            None => return false,
+        };
+        let cos = Vector3f::dot(&it.normal, &sample.dir).abs();
+        match sample.pdf {
+            Some(pdf) => {
+                let pdf_rev = surface.pdf(&it.toward(&sample.dir), &sample.dir, &wo);
+                state.dvc =
+                    mis(cos / pdf) * (state.dvc * mis(pdf_rev) + state.dvcm + radius.vm_weight);
+                state.dvm = mis(cos / pdf)
+                    * (state.dvm * mis(pdf_rev) + state.dvcm * radius.vc_weight + 1.0);
+                state.dvcm = mis(1.0 / pdf);
+            }
+            // 镜面顶点的正反向概率密度相同，相互抵消
+            None => {
+                state.dvcm = 0.0;
+                state.dvc *= mis(cos);
+                state.dvm *= mis(cos);
+            }
+        }
+        if sample.refracted {
+            if it.inside {
+                state.n_stack.pop();
+                state.absorption.pop();
+            } else {
+                state.n_stack.push(it.ior.1);
+                state.absorption.push(material.absorption);
+            }
+        }
+        state.throughput *= sample.weight;
+        state.ray = Ray::new(pos, sample.dir);
+        state.length += 1;
+        true
+    }
+
+    /// 从随机选取的光源出发追踪一条光源子路径，返回路径上的顶点和直接连到相机上的贡献
+    fn light_path(
+        &self,
+        scene: &Scene,
+        camera: &Camera,
+        radius: &Radius,
+        spp: usize,
+        rng: &mut ThreadRng,
+    ) -> (Vec<LightVertex>, Vec<((usize, usize), Color)>) {
+        let mut vertices = vec![];
+        let mut splats = vec![];
+        if scene.lights.is_empty() {
+            return (vertices, splats);
+        }
+        let light_paths = (camera.w * camera.h) as FloatT;
+        let light = &scene.objects[scene.lights[rng.gen_range(0, scene.lights.len())]];
+        let (pos, normal, uv) = light.rand_point(rng);
+        let pdf_pos = 1.0 / (light.area() * scene.lights.len() as FloatT);
+        let dir = light.emit_dir(&normal, rng);
+        let cos = Vector3f::dot(&normal, &dir).abs();
+        let emission_pdf = pdf_pos * light.emit_pdf(&normal, &dir);
+        let mut state = SubPath {
+            ray: Ray::new(pos, dir),
+            throughput: light.flux_at(pos, uv) * (cos / emission_pdf),
+            length: 1,
+            dvcm: mis(pdf_pos / emission_pdf),
+            dvc: mis(cos / emission_pdf),
+            dvm: mis(cos / emission_pdf) * radius.vc_weight,
+            n_stack: vec![scene.n],
+            absorption: vec![],
+        };
+        while let Some(hit) = scene.hit(&state.ray, EPS) {
+            let pos = hit.pos;
+            self.arrive(&mut state, pos, &hit.normal);
+            let surface = hit.object.material.surface;
+            let it = interaction(&hit, &state.ray, &state.n_stack, None);
+            if !surface.is_delta() {
+                let vertex = LightVertex {
+                    pos,
+                    surface,
+                    it,
+                    wi: -state.ray.direction,
+                    throughput: state.throughput,
+                    length: state.length,
+                    dvcm: state.dvcm,
+                    dvc: state.dvc,
+                    dvm: state.dvm,
+                };
+                // 透镜相机无法直接连接
+                if camera.focal.is_none() {
+                    if let Some(splat) =
+                        self.connect_camera(scene, camera, &vertex, radius, light_paths, spp)
+                    {
+                        splats.push(splat);
+                    }
+                }
+                vertices.push(vertex);
+            }
+            if state.length + 2 > self.max_depth + 1 {
+                break;
+            }
+            if !self.scatter(&mut state, pos, &hit.object.material, &it, radius, rng) {
+                break;
+            }
+        }
+        (vertices, splats)
+    }
+
+    /// 将光源子路径的顶点直接连到针孔相机上
+    fn connect_camera(
+        &self,
+        scene: &Scene,
+        camera: &Camera,
+        vertex: &LightVertex,
+        radius: &Radius,
+        light_paths: FloatT,
+        spp: usize,
+    ) -> Option<((usize, usize), Color)> {
+        let (pixel, cos_camera) = camera.project(vertex.pos)?;
+        let d = camera.center - vertex.pos;
+        let dis2 = d.length2();
+        let dir = d / dis2.sqrt();
+        let (f, cos, _, pdf_rev) = eval(&vertex.surface, &vertex.it, &vertex.wi, &dir)?;
+        if !scene.visible(vertex.pos, camera.center) {
+            return None;
+        }
+        // 相机在该点处面积测度下的概率密度，每个像素有 spp 个样本
+        let camera_pdf = camera.pdf(cos_camera) * (camera.w * camera.h) as FloatT * cos / dis2;
+        let w_light = mis(camera_pdf * spp as FloatT / light_paths)
+            * (radius.vm_weight + vertex.dvcm + vertex.dvc * mis(pdf_rev));
+        let weight = 1.0 / (w_light + 1.0);
+        // 每轮只有 light_paths 条光源子路径，与相机的样本数无关
+        Some((
+            pixel,
+            vertex.throughput * f * (weight / light_paths * camera_pdf),
+        ))
+    }
+
+    /// 在非镜面顶点处对光源采样一点并连接
+    fn connect_light(
+        &self,
+        scene: &Scene,
+        state: &SubPath,
+        pos: Vector3f,
+        surface: &Surface,
+        it: &Interaction,
+        radius: &Radius,
+        rng: &mut ThreadRng,
+    ) -> Color {
+        if scene.lights.is_empty() {
+            return Color::empty();
+        }
+        let light = &scene.objects[scene.lights[rng.gen_range(0, scene.lights.len())]];
+        let (p, n, uv) = light.rand_point(rng);
+        let d = p - pos;
+        let dis2 = d.length2();
+        let dir = d / dis2.sqrt();
+        let cos_light = Vector3f::dot(&n, &dir).abs();
+        let (f, cos, pdf, pdf_rev) = match eval(surface, it, &-state.ray.direction, &dir) {
+            Some(ret) => ret,
+            None => return Color::empty(),
+        };
+        if cos_light <= 0.0 || !scene.visible(pos, p) {
+            return Color::empty();
+        }
+        let pdf_pos = 1.0 / (light.area() * scene.lights.len() as FloatT);
+        let direct_pdf = pdf_pos * dis2 / cos_light;
+        let emission_pdf = pdf_pos * light.emit_pdf(&n, &-dir);
+        let w_light = mis(pdf / direct_pdf);
+        let w_camera = mis(emission_pdf * cos / (direct_pdf * cos_light))
+            * (radius.vm_weight + state.dvcm + state.dvc * mis(pdf_rev));
+        let weight = 1.0 / (w_light + 1.0 + w_camera);
+        light.flux_toward(p, uv, &n, &-dir) * f * (weight * cos / direct_pdf)
+    }
+
+    /// 连接相机子路径的顶点和光源子路径的顶点
+    fn connect_vertex(
+        &self,
+        scene: &Scene,
+        state: &SubPath,
+        pos: Vector3f,
+        surface: &Surface,
+        it: &Interaction,
+        vertex: &LightVertex,
+        radius: &Radius,
+    ) -> Color {
+        let d = vertex.pos - pos;
+        let dis2 = d.length2();
+        let dir = d / dis2.sqrt();
+        let (camera_f, cos_camera, camera_pdf, camera_pdf_rev) =
+            match eval(surface, it, &-state.ray.direction, &dir) {
+                Some(ret) => ret,
+                None => return Color::empty(),
+            };
+        let (light_f, cos_light, light_pdf, light_pdf_rev) =
+            match eval(&vertex.surface, &vertex.it, &vertex.wi, &-dir) {
+                Some(ret) => ret,
+                None => return Color::empty(),
+            };
+        if !scene.visible(pos, vertex.pos) {
+            return Color::empty();
+        }
+        // 转换为面积测度
+        let camera_pdf = camera_pdf * cos_light / dis2;
+        let light_pdf = light_pdf * cos_camera / dis2;
+        let w_light =
+            mis(camera_pdf) * (radius.vm_weight + vertex.dvcm + vertex.dvc * mis(light_pdf_rev));
+        let w_camera =
+            mis(light_pdf) * (radius.vm_weight + state.dvcm + state.dvc * mis(camera_pdf_rev));
+        let weight = 1.0 / (w_light + 1.0 + w_camera);
+        vertex.throughput * camera_f * light_f * (weight * cos_camera * cos_light / dis2)
+    }
+
+    /// 合并半径内光源子路径的顶点，返回值还需乘上归一化系数
+    fn merge(
+        &self,
+        state: &SubPath,
+        surface: &Surface,
+        it: &Interaction,
+        vertices: Vec<LightVertex>,
+        radius: &Radius,
+    ) -> Color {
+        vertices
+            .iter()
+            .filter(|vertex| vertex.length + state.length <= self.max_depth + 1)
+            .filter_map(|vertex| {
+                let (f, _, pdf, pdf_rev) = eval(surface, it, &-state.ray.direction, &vertex.wi)?;
+                let w_light = vertex.dvcm * radius.vc_weight + vertex.dvm * mis(pdf);
+                let w_camera = state.dvcm * radius.vc_weight + state.dvm * mis(pdf_rev);
+                let weight = 1.0 / (w_light + 1.0 + w_camera);
+                Some(vertex.throughput * f * weight)
+            })
+            .sum()
+    }
+
+    /// 追踪一条相机子路径，与光源子路径 light_path 连接，并与 photon_map 中的顶点合并
+    fn camera_path(
+        &self,
+        scene: &Scene,
+        camera: &Camera,
+        ray: Ray,
+        light_path: &[LightVertex],
+        photon_map: Option<&kdtree::Node<LightVertex>>,
+        radius: &Radius,
+        spp: usize,
+        rng: &mut ThreadRng,
+    ) -> Color {
+        let light_paths = (camera.w * camera.h) as FloatT;
+        let cos = Vector3f::dot(&camera.direction, &ray.direction);
+        let mut state = SubPath {
+            ray,
+            throughput: Color::ones(),
+            length: 1,
+            // 透镜相机时光源子路径无法连到相机上
+            dvcm: if camera.focal.is_none() {
+                mis(light_paths / (camera.pdf(cos) * light_paths * spp as FloatT))
+            } else {
+                0.0
+            },
+            dvc: 0.0,
+            dvm: 0.0,
+            n_stack: vec![scene.n],
+            absorption: vec![],
+        };
+        let mut color = Color::empty();
+        loop {
+            let hit = match scene.hit(&state.ray, EPS) {
+                Some(hit) => hit,
+                None => {
+                    color += state.throughput * scene.env;
+                    break;
+                }
+            };
+            let (pos, object) = (hit.pos, hit.object);
+            // 自发光的余弦已包含在 emit_pdf 中
+            self.arrive(&mut state, pos, &hit.normal);
+            let flux = object.flux_toward(pos, hit.uv, &hit.normal, &-state.ray.direction);
+            if flux.norm1() > 0.0 {
+                // 无法被采样的光源只能由相机子路径打到
+                let weight = if state.length == 1 || !object.is_light() {
+                    1.0
+                } else {
+                    let pdf_pos = 1.0 / (object.area() * scene.lights.len() as FloatT);
+                    let emission_pdf =
+                        pdf_pos * object.emit_pdf(&hit.normal, &-state.ray.direction);
+                    1.0 / (1.0 + mis(pdf_pos) * state.dvcm + mis(emission_pdf) * state.dvc)
+                };
+                color += state.throughput * flux * weight;
+            }
+            if state.length > self.max_depth {
+                break;
+            }
+            let surface = object.material.surface;
+            let it = interaction(&hit, &state.ray, &state.n_stack, None);
+            if !surface.is_delta() {
+                color += state.throughput
+                    * self.connect_light(scene, &state, pos, &surface, &it, radius, rng);
+                for vertex in light_path {
+                    if vertex.length + state.length + 1 > self.max_depth + 1 {
+                        break;
+                    }
+                    color += state.throughput
+                        * self.connect_vertex(scene, &state, pos, &surface, &it, vertex, radius);
+                }
+                if let Some(photon_map) = photon_map {
+                    color += state.throughput
+                        * self.merge(
+                            &state,
+                            &surface,
+                            &it,
+                            photon_map.within(&pos, radius.radius),
+                            radius,
+                        )
+                        * radius.vm_normalization;
+                }
+            }
+            if !self.scatter(&mut state, pos, &hit.object.material, &it, radius, rng) {
+                break;
+            }
+        }
+        color
+    }
+}
+
+impl Render for VCM {
+    fn render(&self, scene: &Scene, camera: &Camera, name: &str) {
+        let mut pixels = vec![];
+        for i in 0..camera.w {
+            for j in 0..camera.h {
+                pixels.push((i, j));
+            }
+        }
+        let spp = camera.anti_alias;
+        let light_paths = camera.w * camera.h;
+        // 各轮结果之和
+        let mut sum = Image::empty(camera.w, camera.h);
+        for iter in 1..=self.iterations {
+            let now = Instant::now();
+            println!("iteration {}", iter);
+            let r = self.init_radius * (iter as FloatT).powf((self.alpha - 1.0) / 2.0);
+            let eta = PI * r * r * light_paths as FloatT;
+            let radius = Radius {
+                vm_weight: mis(eta),
+                vc_weight: mis(1.0 / eta),
+                vm_normalization: 1.0 / eta,
+                radius: r,
+            };
+
+            println!("light pass");
+            let paths = (0..light_paths)
+                .into_par_iter()
+                .map(|_| {
+                    let mut rng = thread_rng();
+                    self.light_path(scene, camera, &radius, spp, &mut rng)
+                })
+                .collect::<Vec<_>>();
+            let image = Mutex::new(Image::empty(camera.w, camera.h));
+            let mut vertices = vec![];
+            let mut ranges = vec![];
+            for (path, splats) in paths {
+                ranges.push((vertices.len(), vertices.len() + path.len()));
+                vertices.extend(path);
+                let mut image = image.lock().unwrap();
+                for ((x, y), color) in splats {
+                    image.add(x, y, color);
+                }
+            }
+            println!("light vertices: {}", vertices.len());
+            let photon_map = if vertices.is_empty() {
+                None
+            } else {
+                Some(kdtree::new(vertices.clone()))
+            };
+
+            println!("camera pass");
+            pixels.par_iter().enumerate().for_each(|(k, &(i, j))| {
+                let mut rng = thread_rng();
+                let (l, r) = ranges[k];
+                let color = camera
+                    .gen(i, j, &mut rng)
+                    .into_iter()
+                    .map(|ray| {
+                        self.camera_path(
+                            scene,
+                            camera,
+                            ray,
+                            &vertices[l..r],
+                            photon_map.as_deref(),
+                            &radius,
+                            spp,
+                            &mut rng,
+                        )
+                    })
+                    .sum::<Color>()
+                    / spp as FloatT;
+                image.lock().unwrap().add(i, j, color);
+            });
+
+            let image = image.into_inner().unwrap();
+            for i in 0..camera.w {
+                for j in 0..camera.h {
+                    sum.add(i, j, image.at(i, j));
+                }
+            }
+            let mut result = Image::empty(camera.w, camera.h);
+            for i in 0..camera.w {
+                for j in 0..camera.h {
+                    result.set(i, j, sum.at(i, j) / iter as FloatT);
+                }
+            }
+            result.dump(name, true);
+            println!("iteration elapsed: {}ms", now.elapsed().as_millis());
+        }
+    }
+}
@@ -8,7 +8,7 @@ use crate::graphics::Color;
 use crate::math::vector::Vector3f;
 use crate::math::FloatT;
 use crate::utils::trans;
-use image::{open, GenericImageView};
+use image::{open, DynamicImage, GenericImageView};
 use std::mem::{swap, MaybeUninit};
 use std::path::Path;
 use std::sync::Mutex;
@@ -93,6 +93,17 @@ impl Image {
         self.data[y * self.w + x] += color;
     }
 
+    /// 与同样大小的另一张图像对应像素各通道差的平方的平均值
+    pub fn mse(&self, other: &Image) -> FloatT {
+        assert!(self.w == other.w && self.h == other.h);
+        self.data
+            .iter()
+            .zip(other.data.iter())
+            .map(|(a, b)| (*a - *b).length2())
+            .sum::<FloatT>()
+            / (3 * self.data.len()) as FloatT
+    }
+
     // rotate: 是否旋转 180 度
     pub fn dump(&self, name: &str, rotate: bool) {
         let path = format!("output/{}.png", name);
@@ -120,26 +131,31 @@ impl Image {
         println!("...done");
     }
 
+    fn from_buffer(buf: &DynamicImage) -> Self {
+        let mut image = Image::empty(buf.width() as usize, buf.height() as usize);
+        for x in 0..image.w {
+            for y in 0..image.h {
+                let p = buf.get_pixel(x as u32, y as u32);
+                image.set(
+                    x,
+                    y,
+                    Color::new([p[0] as FloatT, p[1] as FloatT, p[2] as FloatT]) / 255.0,
+                );
+            }
+        }
+        image
+    }
+
+    /// 从内存中的图片文件数据读取，格式由数据本身判断
+    pub fn from_memory(data: &[u8]) -> Self {
+        Image::from_buffer(&image::load_from_memory(data).expect("cannot decode image"))
+    }
+
     pub fn load(path: &str) -> Self {
         use Format::*;
         if let Some(format) = infer_format(path) {
             match format {
-                JPG | PNG => {
-                    let buf = open(path).unwrap();
-                    let mut image = Image::empty(buf.width() as usize, buf.height() as usize);
-                    for x in 0..image.w {
-                        for y in 0..image.h {
-                            let p = buf.get_pixel(x as u32, y as u32);
-                            image.set(
-                                x,
-                                y,
-                                Color::new([p[0] as FloatT, p[1] as FloatT, p[2] as FloatT])
-                                    / 255.0,
-                            );
-                        }
-                    }
-                    image
-                }
+                JPG | PNG => Image::from_buffer(&open(path).unwrap()),
                 PPM => unimplemented!(),
             }
         } else {
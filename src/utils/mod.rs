@@ -1,6 +1,6 @@
 use crate::math::vector::Vector3f;
 use crate::math::FloatT;
-use crate::scene::{Camera, Render, Renderer, Scene};
+use crate::scene::{Camera, Gltf, Render, Renderer, Scene};
 use serde::{Deserialize, Deserializer};
 use std::fs;
 use std::io::Write;
@@ -30,15 +30,24 @@ impl Task {
         let data = fs::read_to_string(&path).expect(&format!("Unable to read {}", &path));
         #[derive(Deserialize)]
         struct TaskInfo {
-            pub scene: Scene,
-            pub camera: Camera,
+            pub scene: Option<Scene>,
+            pub camera: Option<Camera>,
+            /// 从 glTF 文件导入场景和相机，同时给出 scene 或 camera 时以后者为准
+            pub gltf: Option<Gltf>,
             pub renderer: Renderer,
             pub num_threads: usize,
         }
         let mut info = serde_json::from_str::<TaskInfo>(&data).expect("cannot convert to json");
+        let (gltf_scene, gltf_camera) = match &info.gltf {
+            Some(gltf) => {
+                let (scene, camera) = gltf.load();
+                (Some(scene), camera)
+            }
+            None => (None, None),
+        };
         Task {
-            scene: info.scene,
-            camera: info.camera,
+            scene: info.scene.or(gltf_scene).expect("no scene in task"),
+            camera: info.camera.or(gltf_camera).expect("no camera in task"),
             renderer: info.renderer,
             num_threads: info.num_threads,
             name: name.to_string(),